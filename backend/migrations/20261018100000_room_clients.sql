@@ -0,0 +1,8 @@
+-- Clients: the user behind each yrs client id that edited a room
+CREATE TABLE IF NOT EXISTS room_clients (
+    room_id text NOT NULL REFERENCES rooms (room_id) ON DELETE CASCADE,
+    client_id bigint NOT NULL,
+    user_id text NOT NULL,
+    first_seen timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (room_id, client_id)
+);
@@ -0,0 +1,6 @@
+-- Forks: the room and seq a room was forked from. No foreign key, forks outlive their parent
+ALTER TABLE rooms
+    ADD COLUMN parent_room_id text;
+
+ALTER TABLE rooms
+    ADD COLUMN forked_at_seq bigint;
@@ -0,0 +1,7 @@
+-- Room metadata for listing rooms: a title, the user who owns it, and when it was created and last edited
+ALTER TABLE rooms
+    ADD COLUMN title text,
+    ADD COLUMN owner text,
+    ADD COLUMN description text,
+    ADD COLUMN created_at timestamptz NOT NULL DEFAULT now(),
+    ADD COLUMN updated_at timestamptz NOT NULL DEFAULT now();
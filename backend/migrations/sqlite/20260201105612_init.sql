@@ -0,0 +1,25 @@
+-- Rooms table: holds per-room metadata, including last_seq counter
+CREATE TABLE IF NOT EXISTS rooms (
+    room_id text PRIMARY KEY,
+    last_seq integer NOT NULL DEFAULT 0
+);
+
+-- Update log: deterministic ordering by (room_id, seq)
+CREATE TABLE IF NOT EXISTS room_updates (
+    room_id text NOT NULL REFERENCES rooms (room_id) ON DELETE CASCADE,
+    seq integer NOT NULL,
+    bytes blob NOT NULL,
+    created_at text NOT NULL,
+    PRIMARY KEY (room_id, seq)
+);
+
+-- Snapshots: multiple per room, identified by covered_through
+CREATE TABLE IF NOT EXISTS room_snapshots (
+    room_id text NOT NULL REFERENCES rooms (room_id) ON DELETE CASCADE,
+    covered_through integer NOT NULL,
+    bytes blob NOT NULL,
+    created_at text NOT NULL,
+    PRIMARY KEY (room_id, covered_through)
+);
+
+CREATE INDEX IF NOT EXISTS room_snapshots_room_cov_idx ON room_snapshots (room_id, covered_through DESC);
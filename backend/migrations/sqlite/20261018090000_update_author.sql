@@ -0,0 +1,2 @@
+-- Author of each update: the user id of whoever made the change, NULL if the server wrote it
+ALTER TABLE room_updates ADD COLUMN author text;
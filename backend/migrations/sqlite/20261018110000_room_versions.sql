@@ -0,0 +1,10 @@
+-- Versions: named positions in a room's log, each backed by a snapshot at exactly that seq
+CREATE TABLE IF NOT EXISTS room_versions (
+    room_id text NOT NULL REFERENCES rooms (room_id) ON DELETE CASCADE,
+    label text NOT NULL,
+    seq integer NOT NULL,
+    author text,
+    note text,
+    created_at text NOT NULL,
+    PRIMARY KEY (room_id, label)
+);
@@ -0,0 +1,22 @@
+-- Room metadata for listing rooms: a title, the user who owns it, and when it was created and last edited
+ALTER TABLE rooms
+    ADD COLUMN title text;
+
+ALTER TABLE rooms
+    ADD COLUMN owner text;
+
+ALTER TABLE rooms
+    ADD COLUMN description text;
+
+-- SQLite cannot add a column defaulting to the current time, existing rooms are backfilled instead
+ALTER TABLE rooms
+    ADD COLUMN created_at text;
+
+ALTER TABLE rooms
+    ADD COLUMN updated_at text;
+
+UPDATE
+    rooms
+SET
+    created_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now'),
+    updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now');
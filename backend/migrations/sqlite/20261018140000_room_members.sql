@@ -0,0 +1,10 @@
+-- Access control: the role each user other than the owner has in a room
+CREATE TABLE IF NOT EXISTS room_members (
+    room_id text NOT NULL REFERENCES rooms (room_id) ON DELETE CASCADE,
+    user_id text NOT NULL,
+    role text NOT NULL CHECK (role IN ('viewer', 'commenter', 'editor', 'owner')),
+    granted_at text NOT NULL,
+    PRIMARY KEY (room_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS room_members_user_id_idx ON room_members (user_id);
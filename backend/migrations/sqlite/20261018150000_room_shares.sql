@@ -0,0 +1,18 @@
+-- Share links: a secret token granting a role in a room, possibly expiring or limited in uses
+CREATE TABLE IF NOT EXISTS room_shares (
+    share_id text PRIMARY KEY,
+    room_id text NOT NULL REFERENCES rooms (room_id) ON DELETE CASCADE,
+    token text NOT NULL UNIQUE,
+    role text NOT NULL CHECK (role IN ('viewer', 'commenter', 'editor', 'owner')),
+    created_by text,
+    expires_at text,
+    max_uses integer,
+    uses integer NOT NULL DEFAULT 0,
+    created_at text NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS room_shares_room_id_idx ON room_shares (room_id);
+
+-- Roles granted through a share go away with it
+ALTER TABLE room_members
+    ADD COLUMN share_id text REFERENCES room_shares (share_id) ON DELETE CASCADE;
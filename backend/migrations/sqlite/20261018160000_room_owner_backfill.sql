@@ -0,0 +1,22 @@
+-- Rooms from before access control are owned by whoever wrote to them first, rooms nobody wrote to keep no owner
+UPDATE
+    rooms
+SET
+    owner = COALESCE((
+        SELECT
+            author
+        FROM room_updates u
+        WHERE
+            u.room_id = rooms.room_id
+            AND author IS NOT NULL
+        ORDER BY seq
+        LIMIT 1), (
+        SELECT
+            user_id
+        FROM room_clients c
+        WHERE
+            c.room_id = rooms.room_id
+        ORDER BY first_seen, client_id
+        LIMIT 1))
+WHERE
+    owner IS NULL;
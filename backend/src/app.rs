@@ -4,14 +4,18 @@ use tower_http::{
     trace::TraceLayer,
 };
 
-use crate::{auth, state::AppState, ws};
+use crate::{auth, health, metrics, rooms, state::AppState, ws};
 
 pub fn router(state: AppState) -> Router {
     let serve_dir =
         ServeDir::new("./build").not_found_service(ServeFile::new("./build/index.html"));
     Router::new()
         .nest("/auth", auth::router())
+        .nest("/rooms", rooms::router())
+        .nest("/share", rooms::share_router())
         .route("/rooms/ws/{room_id}", get(ws::handler::ws_handler))
+        .route("/health", get(health::health))
+        .route("/metrics", get(metrics::metrics))
         .fallback_service(serve_dir)
         .with_state(state)
         .layer(TraceLayer::new_for_http())
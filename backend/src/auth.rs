@@ -11,6 +11,7 @@ use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::routing::get;
 use axum::{Json, Router};
+use axum_extra::extract::cookie::{Cookie, SameSite};
 use openidconnect::core::CoreResponseType;
 use openidconnect::reqwest::async_http_client;
 use openidconnect::{AuthenticationFlow, AuthorizationCode, CsrfToken, Nonce, RedirectUrl};
@@ -24,6 +25,7 @@ use crate::state::AppState;
 
 pub use session::AuthSession;
 pub use session::Session;
+pub use session::is_guest;
 pub use session_store::SessionStore;
 
 #[derive(Debug, Error)]
@@ -186,15 +188,29 @@ impl AuthManager {
                 .map(|s| s.to_string()))
             .unwrap_or_else(|| user_id.clone());
 
-        let session_id = rand_str(64);
-        self.sessions
-            .insert(session_id.clone(), Session::new(user_id, display_name))
-            .await;
+        Ok(self
+            .start_session(Session::new(user_id, display_name))
+            .await)
+    }
 
-        Ok(session_id)
+    /// The [`String`] returned is the id of the new session.
+    pub async fn start_session(&self, session: Session) -> String {
+        let session_id = rand_str(64);
+        self.sessions.insert(session_id.clone(), session).await;
+        session_id
     }
 }
 
+/// The cookie holding the session id of a logged in user.
+pub fn session_cookie(session_id: String) -> Cookie<'static> {
+    Cookie::build(("session", session_id))
+        .path("/")
+        .http_only(true)
+        .secure(true)
+        .same_site(SameSite::Lax)
+        .build()
+}
+
 impl FromRef<AppState> for AuthManager {
     fn from_ref(input: &AppState) -> Self {
         input.auth.clone()
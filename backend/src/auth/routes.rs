@@ -1,11 +1,11 @@
 use axum::extract::{Path, Query};
 use axum::response::{IntoResponse, Redirect};
 use axum::{Json, extract::State};
-use axum_extra::extract::{CookieJar, cookie::Cookie};
+use axum_extra::extract::CookieJar;
 use serde::Deserialize;
 use serde::Serialize;
 
-use crate::auth::AuthError;
+use crate::auth::{AuthError, session_cookie};
 use crate::{auth::session::AuthSession, state::AppState};
 
 #[derive(Serialize)]
@@ -22,12 +22,14 @@ pub async fn providers(State(state): State<AppState>) -> Json<ProviderList> {
 pub struct Me {
     user_id: String,
     display_name: String,
+    guest: bool,
 }
 
 pub async fn me(AuthSession(session): AuthSession) -> Json<Me> {
     Json(Me {
         user_id: session.user_id,
         display_name: session.display_name,
+        guest: session.guest,
     })
 }
 
@@ -58,12 +60,5 @@ pub async fn oidc_callback(
 ) -> Result<impl IntoResponse, AuthError> {
     let session_id = state.auth.finish_login(&provider, q.code, q.state).await?;
 
-    let cookie = Cookie::build(("session", session_id))
-        .path("/")
-        .http_only(true)
-        .secure(true)
-        .same_site(axum_extra::extract::cookie::SameSite::Lax)
-        .build();
-
-    Ok((jar.add(cookie), Redirect::to("/")))
+    Ok((jar.add(session_cookie(session_id)), Redirect::to("/")))
 }
@@ -4,12 +4,23 @@ use axum::{
 };
 use axum_extra::extract::CookieJar;
 
-use crate::auth::AuthManager;
+use crate::auth::{AuthManager, rand_str};
+
+/// Starts the user ids of guests, where logged in users have the name of their OIDC provider.
+const GUEST_PREFIX: &str = "guest|";
+
+/// Whether `user_id` belongs to a guest, see [`Session::guest`].
+pub fn is_guest(user_id: &str) -> bool {
+    user_id.starts_with(GUEST_PREFIX)
+}
 
 #[derive(Clone)]
 pub struct Session {
     pub user_id: String,
     pub display_name: String,
+    /// Set for visitors who came through a share link without logging in. Guests can only use
+    /// the rooms shared with them.
+    pub guest: bool,
 }
 
 impl Session {
@@ -17,6 +28,16 @@ impl Session {
         Self {
             user_id,
             display_name,
+            guest: false,
+        }
+    }
+
+    /// A session for a new guest with a user id of their own.
+    pub fn guest() -> Self {
+        Self {
+            user_id: format!("{GUEST_PREFIX}{}", rand_str(16)),
+            display_name: "Guest".to_string(),
+            guest: true,
         }
     }
 }
@@ -1,4 +1,9 @@
-use std::{collections::HashMap, env, net::IpAddr, path::Path};
+use std::{
+    collections::HashMap,
+    env,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
 
 use config::{Case, ConfigError, Environment, File, FileFormat};
 use serde::{Deserialize, Serialize};
@@ -8,9 +13,13 @@ const DEFAULT_TOML: &str = include_str!("../config/default.toml");
 #[derive(Debug, Deserialize)]
 pub struct Config {
     pub listener: Listener,
-    pub database: Database,
+    pub storage: Storage,
+    /// Required unless `storage.backend` is [`StorageBackend::Memory`].
+    pub database: Option<Database>,
     pub logging: Logging,
     pub oidc: Oidc,
+    pub rooms: Rooms,
+    pub shutdown: Shutdown,
 }
 
 impl Config {
@@ -44,6 +53,20 @@ pub struct Listener {
     pub port: u16,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct Storage {
+    pub backend: StorageBackend,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    Postgres,
+    Sqlite,
+    /// Keeps everything in memory, nothing survives a restart.
+    Memory,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Database {
     pub url: String,
@@ -55,6 +78,83 @@ pub struct Logging {
     pub json: bool,
 }
 
+/// How long a graceful shutdown may take to close connections and write out every room.
+#[derive(Debug, Deserialize)]
+pub struct Shutdown {
+    pub deadline_secs: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Rooms {
+    pub batching: Batching,
+    pub update_retention: UpdateRetention,
+    pub snapshot_retention: SnapshotRetention,
+    pub retry: Retry,
+    pub journal: Journal,
+    pub eviction: Eviction,
+}
+
+/// How updates are grouped before they are written to storage.
+///
+/// A batch is written once `window_ms` has passed since its first update or it holds
+/// `max_updates` updates. With `merge` each batch is stored as a single merged update.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Batching {
+    pub window_ms: u64,
+    pub max_updates: usize,
+    pub merge: bool,
+}
+
+/// How much of the update log to keep once it is covered by a snapshot.
+///
+/// An update is kept if it is among the last `keep_last` updates or younger than `keep_days`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpdateRetention {
+    pub keep_last: u64,
+    pub keep_days: u32,
+}
+
+/// Which snapshots to keep as they age.
+///
+/// Every snapshot younger than `keep_all_hours` is kept, then the newest one per hour until
+/// `keep_hourly_days` and the newest one per day after that. The newest snapshot is always kept.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SnapshotRetention {
+    pub keep_all_hours: u32,
+    pub keep_hourly_days: u32,
+}
+
+/// When rooms without connections are evicted from memory.
+///
+/// A room stays loaded for `idle_timeout_secs` after its last connection closes, so a reconnect
+/// does not have to load it from storage again. At most `max_idle_rooms` are kept like that, the
+/// ones idle for the longest are evicted first.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Eviction {
+    pub idle_timeout_secs: u64,
+    pub max_idle_rooms: usize,
+}
+
+/// Backoff between attempts to write updates that failed to reach storage.
+///
+/// The delay starts at `initial_backoff_ms` and doubles after every failed attempt up to
+/// `max_backoff_ms`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Retry {
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+/// Local files holding updates that could not be written to storage yet.
+///
+/// Each room gets its own file in `dir`. Once a room's journal would grow beyond `max_bytes` it
+/// is dropped and the full document state is written on recovery instead.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Journal {
+    pub dir: PathBuf,
+    pub max_bytes: u64,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Oidc {
     pub external_base_url: String,
@@ -0,0 +1,24 @@
+//! Health check reporting rooms whose updates have not reached storage.
+use axum::Json;
+use axum::extract::State;
+use serde::Serialize;
+
+use crate::state::AppState;
+
+#[derive(Serialize)]
+pub struct Health {
+    /// Whether every live room has written its updates to storage.
+    healthy: bool,
+    /// Live rooms with updates that are only held in memory or in their journal.
+    unhealthy_rooms: Vec<String>,
+}
+
+/// Always responds with `200 OK` while the server is up. A room that can't write its updates
+/// doesn't keep the others from being served, it is only listed.
+pub async fn health(State(state): State<AppState>) -> Json<Health> {
+    let unhealthy_rooms = state.rooms.unhealthy_rooms().await;
+    Json(Health {
+        healthy: unhealthy_rooms.is_empty(),
+        unhealthy_rooms,
+    })
+}
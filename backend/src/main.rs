@@ -2,42 +2,141 @@ mod app;
 mod auth;
 mod config;
 mod db;
+mod health;
 mod logging;
+mod metrics;
 mod rooms;
 mod state;
 mod ws;
 
-use std::net::SocketAddr;
+use std::{net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
 
-use sqlx::PgPool;
+use sqlx::{
+    PgPool, SqlitePool,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode},
+};
 
-use crate::{auth::AuthManager, db::Db};
+use anyhow::Context;
+
+use crate::{
+    auth::AuthManager,
+    config::{Config, StorageBackend},
+    db::Db,
+    rooms::storage::Storage,
+};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let config = config::Config::new()?;
+    let config = Config::new()?;
 
     logging::init_tracing(&config)?;
 
     let addr: SocketAddr = SocketAddr::new(config.listener.ip, config.listener.port);
-    let pool = PgPool::connect(&config.database.url).await?;
-    sqlx::migrate!("./migrations")
-        .run(&pool)
-        .await
-        .expect("Failed to apply database migrations");
+    let storage = connect_storage(&config).await?;
 
     let auth = AuthManager::new(&config).await?;
 
-    let state = state::AppState::new(Db::new(pool), auth).await;
+    let state = state::AppState::new(storage, auth, &config);
+    let rooms = state.rooms.clone();
 
     let app = app::router(state);
 
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
     println!("Listening on {}", listener.local_addr().unwrap());
 
-    axum::serve(listener, app.into_make_service())
-        .await
-        .unwrap();
+    // Once told to, stops accepting connections and waits for ongoing requests. Upgraded
+    // websockets are not among them, the rooms close those below.
+    let (stop, mut stopped) = tokio::sync::watch::channel(());
+    let mut server = tokio::spawn(
+        axum::serve(listener, app.into_make_service())
+            .with_graceful_shutdown(async move {
+                let _ = stopped.changed().await;
+            })
+            .into_future(),
+    );
+
+    tokio::select! {
+        res = &mut server => return Ok(res??),
+        () = shutdown_signal() => {}
+    }
+    stop.send_replace(());
+
+    // Requests and rooms wind down together, and neither may hold up the exit past the deadline.
+    let deadline = Duration::from_secs(config.shutdown.deadline_secs);
+    let drain = async {
+        let (res, ()) = tokio::join!(server, rooms.shutdown(deadline));
+        res
+    };
+    match tokio::time::timeout(deadline, drain).await {
+        Ok(res) => res??,
+        Err(_) => tracing::warn!("shutdown deadline passed, exiting anyway"),
+    }
 
     Ok(())
 }
+
+/// Resolves once the process is asked to stop, by Ctrl+C or SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for Ctrl+C");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => {},
+        () = terminate => {},
+    }
+    tracing::info!("shutting down");
+}
+
+/// Build the [`Storage`] selected by `storage.backend`.
+///
+/// Database backed storage connects to `database.url` and runs its migrations first.
+async fn connect_storage(config: &Config) -> anyhow::Result<Arc<dyn Storage>> {
+    let backend = config.storage.backend;
+    let database_url = || {
+        config
+            .database
+            .as_ref()
+            .map(|d| d.url.as_str())
+            .with_context(|| {
+                format!("database.url is required for the {backend:?} storage backend")
+            })
+    };
+
+    match backend {
+        StorageBackend::Memory => Ok(Arc::new(rooms::InMemoryStorage::new().await)),
+        StorageBackend::Postgres => {
+            let pool = PgPool::connect(database_url()?).await?;
+            sqlx::migrate!("./migrations")
+                .run(&pool)
+                .await
+                .expect("Failed to apply database migrations");
+
+            Ok(Arc::new(rooms::DatabaseStorage::new(Db::new(pool)).await))
+        }
+        StorageBackend::Sqlite => {
+            let opts = SqliteConnectOptions::from_str(database_url()?)?
+                .create_if_missing(true)
+                .journal_mode(SqliteJournalMode::Wal);
+            let pool = SqlitePool::connect_with(opts).await?;
+            sqlx::migrate!("./migrations/sqlite")
+                .run(&pool)
+                .await
+                .expect("Failed to apply database migrations");
+
+            Ok(Arc::new(rooms::SqliteStorage::new(pool).await))
+        }
+    }
+}
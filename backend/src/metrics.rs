@@ -0,0 +1,75 @@
+//! Prometheus text exposition of the server's counters.
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use axum::extract::State;
+
+use crate::state::AppState;
+
+pub async fn metrics(State(state): State<AppState>) -> String {
+    let m = state.rooms.persistence_metrics();
+    let counters: [(&str, &str, &AtomicU64); 9] = [
+        (
+            "dionysus_updates_persisted_total",
+            "Updates appended to storage.",
+            &m.updates_persisted,
+        ),
+        (
+            "dionysus_update_batches_written_total",
+            "Batches of updates appended to storage.",
+            &m.batches_written,
+        ),
+        (
+            "dionysus_updates_overflowed_total",
+            "Updates that did not fit in a room's persistence queue.",
+            &m.updates_overflowed,
+        ),
+        (
+            "dionysus_append_failures_total",
+            "Failed attempts to append updates to storage.",
+            &m.append_failures,
+        ),
+        (
+            "dionysus_full_state_resyncs_total",
+            "Full document states appended to recover unpersisted updates.",
+            &m.full_state_resyncs,
+        ),
+        (
+            "dionysus_updates_journaled_total",
+            "Updates written to a room's journal while storage was unavailable.",
+            &m.updates_journaled,
+        ),
+        (
+            "dionysus_journal_replays_total",
+            "Journals replayed into storage after it recovered.",
+            &m.journal_replays,
+        ),
+        (
+            "dionysus_snapshots_stored_total",
+            "Snapshots stored.",
+            &m.snapshots_stored,
+        ),
+        (
+            "dionysus_snapshot_failures_total",
+            "Failed attempts to store a snapshot.",
+            &m.snapshot_failures,
+        ),
+    ];
+
+    let mut out = String::new();
+    for (name, help, value) in counters {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} counter");
+        let _ = writeln!(out, "{name} {}", value.load(Ordering::Relaxed));
+    }
+
+    let unhealthy = state.rooms.unhealthy_rooms().await.len();
+    let name = "dionysus_rooms_unhealthy";
+    let _ = writeln!(
+        out,
+        "# HELP {name} Live rooms with updates that have not reached storage."
+    );
+    let _ = writeln!(out, "# TYPE {name} gauge");
+    let _ = writeln!(out, "{name} {unhealthy}");
+    out
+}
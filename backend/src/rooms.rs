@@ -1,10 +1,105 @@
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{
+    Json, Router,
+    routing::{delete, get, post, put},
+};
+use serde::Serialize;
+
+use crate::state::AppState;
+
+#[cfg(test)]
+mod bench;
+pub mod blame;
+#[cfg(test)]
+mod conformance;
+pub mod diff;
 pub mod error;
+pub mod history;
 mod in_memory;
+mod journal;
 pub mod manager;
+pub mod merge;
+pub mod persistence;
 mod repo;
+mod retention;
+mod routes;
+mod sqlite;
 pub mod storage;
 
 pub use error::Error;
 pub use in_memory::InMemoryStorage;
-pub use manager::RoomManager;
+pub use manager::{CloseReason, RoomLease, RoomManager};
 pub use repo::DatabaseStorage;
+pub use sqlite::SqliteStorage;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(routes::list_rooms).post(routes::create_room))
+        .route(
+            "/{room_id}",
+            get(routes::get_room)
+                .patch(routes::update_room)
+                .delete(routes::delete_room),
+        )
+        .route("/{room_id}/text", get(routes::text_at))
+        .route("/{room_id}/restore", post(routes::restore))
+        .route("/{room_id}/blame", get(routes::blame))
+        .route("/{room_id}/diff", get(routes::diff))
+        .route("/{room_id}/fork", post(routes::fork))
+        .route("/{room_id}/merge", post(routes::merge))
+        .route(
+            "/{room_id}/versions",
+            get(routes::list_versions).post(routes::create_version),
+        )
+        .route(
+            "/{room_id}/versions/{label}",
+            delete(routes::delete_version),
+        )
+        .route(
+            "/{room_id}/versions/{label}/text",
+            get(routes::version_text),
+        )
+        .route("/{room_id}/members", get(routes::list_members))
+        .route(
+            "/{room_id}/members/{user_id}",
+            put(routes::set_member).delete(routes::remove_member),
+        )
+        .route(
+            "/{room_id}/shares",
+            get(routes::list_shares).post(routes::create_share),
+        )
+        .route("/{room_id}/shares/{share_id}", delete(routes::delete_share))
+}
+
+/// Share links, nested under `/share` as they are handed out to people without an account.
+pub fn share_router() -> Router<AppState> {
+    Router::new().route("/{token}", get(routes::redeem_share))
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: &'static str,
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> axum::response::Response {
+        let (status, message) = match self {
+            Error::NotFound => (StatusCode::NOT_FOUND, "not_found"),
+            Error::AlreadyExists => (StatusCode::CONFLICT, "already_exists"),
+            Error::Forbidden => (StatusCode::FORBIDDEN, "forbidden"),
+            Error::InvalidArgument(ref reason) => {
+                tracing::debug!(reason, "invalid argument");
+                (StatusCode::BAD_REQUEST, "invalid_argument")
+            }
+            Error::HistoryPruned => (StatusCode::GONE, "history_pruned"),
+            Error::ShuttingDown => (StatusCode::SERVICE_UNAVAILABLE, "shutting_down"),
+            Error::Decoding(_) | Error::Backend { .. } => {
+                tracing::error!(error = ?self, "room error");
+                (StatusCode::INTERNAL_SERVER_ERROR, "internal")
+            }
+        };
+
+        (status, Json(ErrorBody { error: message })).into_response()
+    }
+}
@@ -0,0 +1,137 @@
+//! Throughput of the persistence pipeline with and without batching.
+//!
+//! These are benchmarks rather than tests and are ignored by default. Run them with
+//! `cargo test --release bench -- --ignored --nocapture`, the Postgres one needs a
+//! `DATABASE_URL` like the conformance tests.
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use tokio::sync::RwLock;
+use tokio::time::{Duration, Instant};
+use yrs::sync::Awareness;
+use yrs::{Doc, Text, Transact};
+use yrs_axum::AwarenessRef;
+
+use crate::config::{self, Batching, Retry, SnapshotRetention, UpdateRetention};
+use crate::rooms::journal::Journal;
+use crate::rooms::persistence::{self, PersistenceMetrics, PersistenceSettings};
+use crate::rooms::storage::{CreateRoomOptions, Storage};
+
+const KEYSTROKES: usize = 2000;
+
+fn settings(batching: Batching) -> PersistenceSettings {
+    PersistenceSettings {
+        // Large enough to never overflow, so only the write path is measured.
+        queue_capacity: KEYSTROKES,
+        snapshot_every_n_updates: 100,
+        batching,
+        update_retention: UpdateRetention {
+            keep_last: 1000,
+            keep_days: 7,
+        },
+        snapshot_retention: SnapshotRetention {
+            keep_all_hours: 24,
+            keep_hourly_days: 7,
+        },
+        retry: Retry {
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30000,
+        },
+        journal: config::Journal {
+            dir: std::env::temp_dir().join("dionysus-bench-journal"),
+            max_bytes: u64::MAX,
+        },
+    }
+}
+
+/// Type `KEYSTROKES` characters into a fresh room and time until all of them are persisted.
+async fn time_keystrokes(storage: Arc<dyn Storage>, room_id: &str, batching: Batching) -> Duration {
+    storage
+        .create_room(room_id, CreateRoomOptions::default())
+        .await
+        .unwrap();
+
+    let doc = Doc::new();
+    let text = doc.get_or_insert_text("codemirror");
+    let awareness: AwarenessRef = Arc::new(RwLock::new(Awareness::new(doc.clone())));
+    let metrics = Arc::new(PersistenceMetrics::default());
+    let settings = settings(batching);
+    let journal = Journal::open(&settings.journal.dir, room_id, settings.journal.max_bytes)
+        .await
+        .unwrap();
+    let _sub = persistence::spawn(
+        storage,
+        room_id,
+        &doc,
+        Doc::new(),
+        awareness.clone(),
+        journal,
+        settings,
+        metrics.clone(),
+    );
+
+    let start = Instant::now();
+    for _ in 0..KEYSTROKES {
+        let awareness = awareness.write().await;
+        text.push(&mut awareness.doc().transact_mut(), "a");
+    }
+
+    while metrics.updates_persisted.load(Ordering::Relaxed) < KEYSTROKES as u64 {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    start.elapsed()
+}
+
+async fn compare(storage: Arc<dyn Storage>) {
+    let cases = [
+        (
+            "unbatched",
+            Batching {
+                window_ms: 0,
+                max_updates: 1,
+                merge: false,
+            },
+        ),
+        (
+            "batched",
+            Batching {
+                window_ms: 50,
+                max_updates: 256,
+                merge: false,
+            },
+        ),
+        (
+            "batched+merged",
+            Batching {
+                window_ms: 50,
+                max_updates: 256,
+                merge: true,
+            },
+        ),
+    ];
+
+    for (name, batching) in cases {
+        let elapsed = time_keystrokes(storage.clone(), name, batching).await;
+        let per_sec = KEYSTROKES as f64 / elapsed.as_secs_f64();
+        println!("{name:>15}: {KEYSTROKES} updates in {elapsed:>10.2?} ({per_sec:>9.0} updates/s)");
+    }
+}
+
+#[tokio::test]
+#[ignore = "benchmark"]
+async fn bench_in_memory() {
+    compare(Arc::new(crate::rooms::InMemoryStorage::new().await)).await;
+}
+
+#[sqlx::test(migrations = "./migrations/sqlite")]
+#[ignore = "benchmark"]
+async fn bench_sqlite(pool: sqlx::SqlitePool) {
+    compare(Arc::new(crate::rooms::SqliteStorage::new(pool).await)).await;
+}
+
+#[sqlx::test(migrations = "./migrations")]
+#[ignore = "benchmark, needs DATABASE_URL pointing to a Postgres server"]
+async fn bench_postgres(pool: sqlx::PgPool) {
+    let db = crate::db::Db::new(pool);
+    compare(Arc::new(crate::rooms::DatabaseStorage::new(db).await)).await;
+}
@@ -0,0 +1,382 @@
+//! Which user inserted each part of a room's text, and when.
+//!
+//! Every character of a yrs text is identified by the client id of the document that inserted
+//! it and a clock counting that client's insertions. The client id leads to the user through the
+//! clients recorded for the room, the clock to the update in the log that inserted it, which
+//! gives the time. Parts inserted by updates that retention already removed have no time.
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use yrs::block::ClientID;
+use yrs::types::Value;
+use yrs::types::text::YChange;
+use yrs::updates::decoder::Decode;
+use yrs::updates::encoder::Encode;
+use yrs::{Any, Doc, ReadTxn, Snapshot, StateVector, Text, Transact, Update};
+
+use crate::rooms::error::Error;
+use crate::rooms::history::TEXT;
+use crate::rooms::storage::{ClientInfo, UpdateEntry};
+
+/// One line of the text.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BlameLine {
+    /// Starting at 1.
+    pub line: usize,
+    pub text: String,
+    pub runs: Vec<BlameRun>,
+}
+
+/// Consecutive characters of a line inserted by the same user at the same time.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BlameRun {
+    pub text: String,
+    /// User id, `None` if the server wrote it on its own or the user is unknown.
+    pub author: Option<String>,
+    /// `None` if the update that inserted it is no longer in the log.
+    pub inserted_at: Option<DateTime<Utc>>,
+}
+
+/// A range of clocks of one client inserted by one update.
+struct Insertion {
+    start: u32,
+    end: u32,
+    author: Option<String>,
+    at: DateTime<Utc>,
+}
+
+/// Blame every line of the text of `doc`, using the `clients` of its room and the update `log`
+/// in seq order.
+pub fn blame(
+    doc: &Doc,
+    clients: &[ClientInfo],
+    log: &[UpdateEntry],
+) -> Result<Vec<BlameLine>, Error> {
+    let users: HashMap<ClientID, &str> = clients
+        .iter()
+        .map(|c| (c.client_id, c.user_id.as_str()))
+        .collect();
+    let insertions = insertions(log)?;
+
+    let text = doc.get_or_insert_text(TEXT);
+    let mut txn = doc.transact_mut();
+    let now = txn.snapshot();
+    // Against an empty snapshot every visible item is an addition, tagged with its id.
+    let chunks = text.diff_range(
+        &mut txn,
+        Some(&now),
+        Some(&Snapshot::default()),
+        YChange::identity,
+    );
+
+    let mut lines = vec![BlameLine::new(1)];
+    for chunk in chunks {
+        let (Value::Any(Any::String(s)), Some(change)) = (chunk.insert, chunk.ychange) else {
+            continue;
+        };
+        let client = change.id.client;
+        let known = insertions.get(&client).map_or(&[][..], Vec::as_slice);
+
+        // An item can span several updates, split it where they meet.
+        let mut rest = &*s;
+        let mut clock = change.id.clock;
+        while !rest.is_empty() {
+            let (insertion, end) = lookup(known, clock);
+            let (part, tail) = split_utf16(rest, end - clock);
+            clock += part.encode_utf16().count() as u32;
+            rest = tail;
+
+            let author = users
+                .get(&client)
+                .map(|user| user.to_string())
+                .or_else(|| insertion.and_then(|i| i.author.clone()));
+            let inserted_at = insertion.map(|i| i.at);
+
+            for (i, segment) in part.split('\n').enumerate() {
+                if i > 0 {
+                    lines.push(BlameLine::new(lines.len() + 1));
+                }
+                let line = lines.last_mut().expect("there is always a line");
+                line.push(segment, &author, inserted_at);
+            }
+        }
+    }
+
+    Ok(lines)
+}
+
+impl BlameLine {
+    fn new(line: usize) -> Self {
+        Self {
+            line,
+            text: String::new(),
+            runs: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, text: &str, author: &Option<String>, inserted_at: Option<DateTime<Utc>>) {
+        if text.is_empty() {
+            return;
+        }
+        self.text.push_str(text);
+
+        match self.runs.last_mut() {
+            Some(run) if run.author == *author && run.inserted_at == inserted_at => {
+                run.text.push_str(text);
+            }
+            _ => self.runs.push(BlameRun {
+                text: text.to_string(),
+                author: author.clone(),
+                inserted_at,
+            }),
+        }
+    }
+}
+
+/// The clock ranges each update in `log` inserted, per client and in clock order.
+fn insertions(log: &[UpdateEntry]) -> Result<HashMap<ClientID, Vec<Insertion>>, Error> {
+    let pruned = log.first().is_some_and(|u| u.seq > 1);
+
+    let mut insertions: HashMap<ClientID, Vec<Insertion>> = HashMap::new();
+    for entry in log {
+        let update = Update::decode_v1(&entry.bytes)?;
+        for (&client, &end) in update.state_vector().iter() {
+            let known = insertions.entry(client).or_default();
+            // A client inserts in clock order, so an update starts where the previous one ended.
+            // Only the first one left after pruning may start later.
+            let start = match known.last() {
+                Some(prev) if prev.end >= end => continue,
+                Some(prev) => prev.end,
+                None if pruned => first_clock(&entry.bytes, client, end)?,
+                None => 0,
+            };
+            known.push(Insertion {
+                start,
+                end,
+                author: entry.author.clone(),
+                at: entry.created_at,
+            });
+        }
+    }
+
+    Ok(insertions)
+}
+
+/// The insertion covering `clock` if it is still in the log, and the clock it ends at.
+fn lookup(known: &[Insertion], clock: u32) -> (Option<&Insertion>, u32) {
+    let i = known.partition_point(|ins| ins.end <= clock);
+    match known.get(i) {
+        Some(ins) if ins.start <= clock => (Some(ins), ins.end),
+        Some(ins) => (None, ins.start),
+        None => (None, u32::MAX),
+    }
+}
+
+/// The first clock of `client` in `update`, which ends at `end`.
+///
+/// yrs does not expose the blocks of an update, but trimming the update at a clock leaves it
+/// unchanged exactly as long as that clock is not past the first one.
+fn first_clock(update: &[u8], client: ClientID, end: u32) -> Result<u32, Error> {
+    let trim = |clock| {
+        let mut sv = StateVector::default();
+        sv.set_max(client, clock);
+        yrs::diff_updates_v1(update, &sv.encode_v1())
+    };
+    let whole = trim(0)?;
+
+    let (mut lo, mut hi) = (0, end.saturating_sub(1));
+    while lo < hi {
+        let mid = lo + (hi - lo).div_ceil(2);
+        if trim(mid)? == whole {
+            lo = mid;
+        } else {
+            hi = mid - 1;
+        }
+    }
+    Ok(lo)
+}
+
+/// Split `s` after `units` UTF-16 code units, the unit yrs counts text clocks in.
+fn split_utf16(s: &str, units: u32) -> (&str, &str) {
+    let mut count = 0;
+    for (i, c) in s.char_indices() {
+        if count >= units {
+            return s.split_at(i);
+        }
+        count += c.len_utf16() as u32;
+    }
+    (s, "")
+}
+
+#[cfg(test)]
+mod tests {
+    use yrs::updates::decoder::Decode;
+    use yrs::{Doc, ReadTxn, Text, Transact};
+
+    use crate::rooms::InMemoryStorage;
+    use crate::rooms::blame::{self, BlameLine};
+    use crate::rooms::history::{self, At};
+    use crate::rooms::storage::{
+        CreateRoomOptions, LoadUpdatesOptions, PruneUpdatesOptions, Snapshot, Storage,
+    };
+
+    const ROOM: &str = "room";
+
+    /// A writer with their own document, editing the room through `storage`.
+    struct Writer {
+        user_id: &'static str,
+        doc: Doc,
+    }
+
+    impl Writer {
+        async fn join(storage: &InMemoryStorage, user_id: &'static str, client_id: u64) -> Self {
+            storage
+                .record_client(ROOM, client_id, user_id)
+                .await
+                .unwrap();
+
+            let doc = Doc::with_client_id(client_id);
+            let state = history::doc_at(storage, ROOM, At::Seq(last_seq(storage).await))
+                .await
+                .unwrap()
+                .transact()
+                .encode_state_as_update_v1(&yrs::StateVector::default());
+            doc.transact_mut()
+                .apply_update(yrs::Update::decode_v1(&state).unwrap());
+
+            Self { user_id, doc }
+        }
+
+        /// Append `chunk` to the text and write the update to the log.
+        async fn type_text(&self, storage: &InMemoryStorage, chunk: &str) {
+            let update = {
+                let text = self.doc.get_or_insert_text(history::TEXT);
+                let mut txn = self.doc.transact_mut();
+                let before = txn.state_vector();
+                text.push(&mut txn, chunk);
+                txn.encode_state_as_update_v1(&before)
+            };
+            storage
+                .append_update(ROOM, &update, Some(self.user_id))
+                .await
+                .unwrap();
+        }
+    }
+
+    async fn last_seq(storage: &InMemoryStorage) -> u64 {
+        storage.get_room_info(ROOM).await.unwrap().unwrap().last_seq
+    }
+
+    async fn room() -> InMemoryStorage {
+        let storage = InMemoryStorage::new().await;
+        storage
+            .create_room(ROOM, CreateRoomOptions::default())
+            .await
+            .unwrap();
+        storage
+    }
+
+    async fn blame(storage: &InMemoryStorage) -> Vec<BlameLine> {
+        let doc = history::doc_at(storage, ROOM, At::Seq(last_seq(storage).await))
+            .await
+            .unwrap();
+        let clients = storage.list_clients(ROOM).await.unwrap();
+        let log = storage
+            .load_updates(ROOM, LoadUpdatesOptions::default())
+            .await
+            .unwrap();
+        blame::blame(&doc, &clients, &log).unwrap()
+    }
+
+    /// The runs of `line` as (text, author, whether its time is known).
+    fn runs(line: &BlameLine) -> Vec<(&str, Option<&str>, bool)> {
+        line.runs
+            .iter()
+            .map(|r| {
+                (
+                    r.text.as_str(),
+                    r.author.as_deref(),
+                    r.inserted_at.is_some(),
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn blame_attributes_runs_to_writers_and_updates() {
+        let storage = room().await;
+
+        let alice = Writer::join(&storage, "alice", 1).await;
+        // Both end up in one item, which has to be split at a clock counted in UTF-16.
+        alice.type_text(&storage, "INT. 🌙 BAR").await;
+        alice.type_text(&storage, " - NIGHT\n").await;
+
+        let bob = Writer::join(&storage, "bob", 2).await;
+        bob.type_text(&storage, "\nJOSÉ\nAnother.").await;
+
+        let lines = blame(&storage).await;
+        let texts: Vec<&str> = lines.iter().map(|l| l.text.as_str()).collect();
+        assert_eq!(texts, ["INT. 🌙 BAR - NIGHT", "", "JOSÉ", "Another."]);
+
+        let log = storage
+            .load_updates(ROOM, LoadUpdatesOptions::default())
+            .await
+            .unwrap();
+        let times: Vec<_> = lines[0].runs.iter().map(|r| r.inserted_at).collect();
+        assert_eq!(times, [Some(log[0].created_at), Some(log[1].created_at)]);
+        assert_eq!(
+            runs(&lines[0]),
+            [
+                ("INT. 🌙 BAR", Some("alice"), true),
+                (" - NIGHT", Some("alice"), true)
+            ]
+        );
+        assert!(lines[1].runs.is_empty());
+        assert_eq!(runs(&lines[2]), [("JOSÉ", Some("bob"), true)]);
+        assert_eq!(lines[3].runs[0].inserted_at, Some(log[2].created_at));
+    }
+
+    #[tokio::test]
+    async fn blame_has_no_time_for_pruned_updates() {
+        let storage = room().await;
+
+        let alice = Writer::join(&storage, "alice", 1).await;
+        alice.type_text(&storage, "FADE IN:\n").await;
+        alice.type_text(&storage, "\nINT. ").await;
+
+        let bytes = history::doc_at(&storage, ROOM, At::Seq(2))
+            .await
+            .unwrap()
+            .transact()
+            .encode_state_as_update_v1(&yrs::StateVector::default());
+        storage
+            .store_snapshot(
+                ROOM,
+                Snapshot {
+                    covered_through: 2,
+                    bytes,
+                },
+            )
+            .await
+            .unwrap();
+        storage
+            .prune_updates_through(ROOM, 1, PruneUpdatesOptions::default())
+            .await
+            .unwrap();
+
+        alice.type_text(&storage, "HOUSE").await;
+
+        let lines = blame(&storage).await;
+        assert_eq!(runs(&lines[0]), [("FADE IN:", Some("alice"), false)]);
+        assert_eq!(runs(&lines[1]), []);
+        // The first update left starts past the pruned ones.
+        assert_eq!(
+            runs(&lines[2]),
+            [
+                ("INT. ", Some("alice"), true),
+                ("HOUSE", Some("alice"), true)
+            ]
+        );
+    }
+}
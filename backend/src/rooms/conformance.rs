@@ -0,0 +1,781 @@
+//! Conformance tests every [`Storage`] implementation has to pass.
+//!
+//! Each case is a generic function over [`Storage`] and [`conformance_tests!`] instantiates it
+//! for every backend. The Postgres cases need a `DATABASE_URL` to a server where throwaway
+//! databases can be created and are therefore ignored by default, run them with
+//! `cargo test -- --ignored`.
+use chrono::{TimeDelta, Utc};
+
+use crate::rooms::Error;
+use crate::rooms::storage::{
+    CreateRoomOptions, ForkPoint, LoadUpdatesOptions, LogSeq, NewShare, NewUpdate, NewVersion,
+    PruneUpdatesOptions, Role, RoomMetadataUpdate, Snapshot, Storage,
+};
+
+const ROOM: &str = "conformance-room";
+
+async fn create(storage: &impl Storage, room_id: &str) {
+    storage
+        .create_room(room_id, CreateRoomOptions::default())
+        .await
+        .unwrap();
+}
+
+async fn append_n(storage: &impl Storage, room_id: &str, n: u8) {
+    for i in 0..n {
+        storage.append_update(room_id, &[i], None).await.unwrap();
+    }
+}
+
+async fn seqs(
+    storage: &impl Storage,
+    room_id: &str,
+    from: Option<LogSeq>,
+    to: Option<LogSeq>,
+) -> Vec<LogSeq> {
+    storage
+        .load_updates(room_id, LoadUpdatesOptions { from, to })
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|u| u.seq)
+        .collect()
+}
+
+fn update(bytes: &[u8], author: Option<&str>) -> NewUpdate {
+    NewUpdate {
+        bytes: bytes.to_vec(),
+        author: author.map(str::to_string),
+    }
+}
+
+fn new_share(
+    share_id: &str,
+    token: &str,
+    expires_at: Option<chrono::DateTime<Utc>>,
+    max_uses: Option<u32>,
+) -> NewShare {
+    NewShare {
+        share_id: share_id.to_string(),
+        token: token.to_string(),
+        role: Role::Viewer,
+        created_by: Some("alice".to_string()),
+        expires_at,
+        max_uses,
+    }
+}
+
+fn snapshot(covered_through: LogSeq, bytes: &[u8]) -> Snapshot {
+    Snapshot {
+        covered_through,
+        bytes: bytes.to_vec(),
+    }
+}
+
+async fn sequence_starts_at_one(storage: impl Storage) {
+    create(&storage, ROOM).await;
+    assert_eq!(
+        storage.get_room_info(ROOM).await.unwrap().unwrap().last_seq,
+        0
+    );
+
+    assert_eq!(storage.append_update(ROOM, b"a", None).await.unwrap(), 1);
+    assert_eq!(storage.append_update(ROOM, b"b", None).await.unwrap(), 2);
+    let batch = [update(b"c", None), update(b"d", None), update(b"e", None)];
+    assert_eq!(storage.append_updates(ROOM, &batch).await.unwrap(), (3, 5));
+
+    assert_eq!(
+        storage.get_room_info(ROOM).await.unwrap().unwrap().last_seq,
+        5
+    );
+
+    let updates = storage
+        .load_updates(ROOM, LoadUpdatesOptions::default())
+        .await
+        .unwrap();
+    let log: Vec<(LogSeq, &[u8])> = updates.iter().map(|u| (u.seq, &u.bytes[..])).collect();
+    assert_eq!(
+        log,
+        [(1, &b"a"[..]), (2, b"b"), (3, b"c"), (4, b"d"), (5, b"e")]
+    );
+}
+
+async fn authors_are_kept(storage: impl Storage) {
+    create(&storage, ROOM).await;
+
+    storage
+        .append_update(ROOM, b"a", Some("alice"))
+        .await
+        .unwrap();
+    let batch = [
+        update(b"b", Some("bob")),
+        update(b"c", None),
+        update(b"d", Some("alice")),
+    ];
+    storage.append_updates(ROOM, &batch).await.unwrap();
+
+    let updates = storage
+        .load_updates(ROOM, LoadUpdatesOptions::default())
+        .await
+        .unwrap();
+    let authors: Vec<Option<&str>> = updates.iter().map(|u| u.author.as_deref()).collect();
+    assert_eq!(authors, [Some("alice"), Some("bob"), None, Some("alice")]);
+}
+
+async fn record_client_keeps_first_user(storage: impl Storage) {
+    create(&storage, ROOM).await;
+    assert!(storage.list_clients(ROOM).await.unwrap().is_empty());
+
+    assert!(storage.record_client(ROOM, 7, "alice").await.unwrap());
+    assert!(!storage.record_client(ROOM, 7, "mallory").await.unwrap());
+    assert!(storage.record_client(ROOM, 7, "alice").await.unwrap());
+    // Client ids are random u64s in yrs, including ones that do not fit an i64.
+    assert!(storage.record_client(ROOM, u64::MAX, "bob").await.unwrap());
+
+    let clients: Vec<(u64, String)> = storage
+        .list_clients(ROOM)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|c| (c.client_id, c.user_id))
+        .collect();
+    assert_eq!(clients.len(), 2);
+    assert!(clients.contains(&(7, "alice".to_string())));
+    assert!(clients.contains(&(u64::MAX, "bob".to_string())));
+}
+
+fn version(label: &str, seq: LogSeq) -> NewVersion {
+    NewVersion {
+        label: label.to_string(),
+        seq,
+        author: Some("alice".to_string()),
+        note: None,
+    }
+}
+
+async fn versions_have_unique_labels(storage: impl Storage) {
+    create(&storage, ROOM).await;
+    append_n(&storage, ROOM, 3).await;
+
+    let mut second = version("second draft", 3);
+    second.note = Some("after notes".to_string());
+    let created = storage.create_version(ROOM, second).await.unwrap();
+    assert_eq!(created.note.as_deref(), Some("after notes"));
+    storage
+        .create_version(ROOM, version("first draft", 1))
+        .await
+        .unwrap();
+
+    let res = storage
+        .create_version(ROOM, version("first draft", 2))
+        .await;
+    assert!(matches!(res, Err(Error::AlreadyExists)), "{res:?}");
+
+    let listed = storage.list_versions(ROOM).await.unwrap();
+    let labels: Vec<(&str, LogSeq)> = listed.iter().map(|v| (v.label.as_str(), v.seq)).collect();
+    assert_eq!(labels, [("first draft", 1), ("second draft", 3)]);
+    assert_eq!(listed[1], created);
+    assert_eq!(
+        storage.get_version(ROOM, "second draft").await.unwrap(),
+        Some(created)
+    );
+
+    storage.delete_version(ROOM, "first draft").await.unwrap();
+    assert_eq!(
+        storage.get_version(ROOM, "first draft").await.unwrap(),
+        None
+    );
+    let res = storage.delete_version(ROOM, "first draft").await;
+    assert!(matches!(res, Err(Error::NotFound)), "{res:?}");
+    assert_eq!(storage.list_versions(ROOM).await.unwrap().len(), 1);
+}
+
+async fn members_have_one_role_per_room(storage: impl Storage) {
+    create(&storage, ROOM).await;
+    create(&storage, "other-room").await;
+    assert_eq!(storage.get_member(ROOM, "bob").await.unwrap(), None);
+
+    let granted = storage
+        .set_member(ROOM, "bob", Role::Viewer, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        storage.get_member(ROOM, "bob").await.unwrap(),
+        Some(granted)
+    );
+
+    // Granting again replaces the role.
+    storage
+        .set_member(ROOM, "bob", Role::Editor, None)
+        .await
+        .unwrap();
+    storage
+        .set_member(ROOM, "alice", Role::Owner, None)
+        .await
+        .unwrap();
+    storage
+        .set_member("other-room", "bob", Role::Commenter, None)
+        .await
+        .unwrap();
+    let members: Vec<_> = storage
+        .list_members(ROOM)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|m| (m.user_id, m.role))
+        .collect();
+    assert_eq!(
+        members,
+        [
+            ("alice".to_string(), Role::Owner),
+            ("bob".to_string(), Role::Editor)
+        ]
+    );
+
+    let mut memberships = storage.list_memberships("bob").await.unwrap();
+    memberships.sort();
+    assert_eq!(
+        memberships,
+        [
+            (ROOM.to_string(), Role::Editor),
+            ("other-room".to_string(), Role::Commenter)
+        ]
+    );
+
+    storage.remove_member(ROOM, "bob").await.unwrap();
+    assert_eq!(storage.get_member(ROOM, "bob").await.unwrap(), None);
+    assert!(matches!(
+        storage.remove_member(ROOM, "bob").await,
+        Err(Error::NotFound)
+    ));
+}
+
+async fn shares_are_used_up_and_revoked(storage: impl Storage) {
+    create(&storage, ROOM).await;
+    let now = Utc::now();
+
+    let once = storage
+        .create_share(ROOM, new_share("once", "once-token", None, Some(1)))
+        .await
+        .unwrap();
+    assert_eq!(once.uses, 0);
+    assert!(matches!(
+        storage
+            .create_share(ROOM, new_share("other", "once-token", None, None))
+            .await,
+        Err(Error::AlreadyExists)
+    ));
+
+    let used = storage.use_share("once-token", now).await.unwrap();
+    assert_eq!((used.share_id.as_str(), used.uses), ("once", 1));
+    assert!(matches!(
+        storage.use_share("once-token", now).await,
+        Err(Error::NotFound)
+    ));
+
+    let hour = TimeDelta::hours(1);
+    storage
+        .create_share(
+            ROOM,
+            new_share("later", "later-token", Some(now + hour), None),
+        )
+        .await
+        .unwrap();
+    storage.use_share("later-token", now).await.unwrap();
+    assert_eq!(storage.use_share("later-token", now).await.unwrap().uses, 2);
+    assert!(matches!(
+        storage.use_share("later-token", now + hour).await,
+        Err(Error::NotFound)
+    ));
+
+    let ids: Vec<_> = storage
+        .list_shares(ROOM)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|s| (s.share_id, s.uses))
+        .collect();
+    assert_eq!(ids, [("once".to_string(), 1), ("later".to_string(), 2)]);
+
+    // Revoking a share takes away the roles granted through it, but not others.
+    storage
+        .set_member(ROOM, "bob", Role::Viewer, Some("later"))
+        .await
+        .unwrap();
+    storage
+        .set_member(ROOM, "carol", Role::Editor, None)
+        .await
+        .unwrap();
+    assert!(matches!(
+        storage
+            .set_member(ROOM, "dave", Role::Viewer, Some("missing"))
+            .await,
+        Err(Error::NotFound)
+    ));
+    assert_eq!(
+        storage
+            .get_member(ROOM, "bob")
+            .await
+            .unwrap()
+            .unwrap()
+            .share_id
+            .as_deref(),
+        Some("later")
+    );
+
+    storage.delete_share(ROOM, "later").await.unwrap();
+    assert_eq!(storage.get_member(ROOM, "bob").await.unwrap(), None);
+    assert!(storage.get_member(ROOM, "carol").await.unwrap().is_some());
+    assert_eq!(storage.list_shares(ROOM).await.unwrap().len(), 1);
+    assert!(matches!(
+        storage.delete_share(ROOM, "later").await,
+        Err(Error::NotFound)
+    ));
+}
+
+async fn append_updates_rejects_empty(storage: impl Storage) {
+    create(&storage, ROOM).await;
+
+    let res = storage.append_updates(ROOM, &[]).await;
+    assert!(matches!(res, Err(Error::InvalidArgument(_))), "{res:?}");
+    assert_eq!(
+        storage.get_room_info(ROOM).await.unwrap().unwrap().last_seq,
+        0
+    );
+}
+
+async fn load_updates_ranges_are_inclusive(storage: impl Storage) {
+    create(&storage, ROOM).await;
+    append_n(&storage, ROOM, 5).await;
+
+    assert_eq!(seqs(&storage, ROOM, None, None).await, [1, 2, 3, 4, 5]);
+    assert_eq!(seqs(&storage, ROOM, Some(2), Some(4)).await, [2, 3, 4]);
+    assert_eq!(seqs(&storage, ROOM, Some(4), None).await, [4, 5]);
+    assert_eq!(seqs(&storage, ROOM, None, Some(2)).await, [1, 2]);
+    assert_eq!(seqs(&storage, ROOM, Some(3), Some(3)).await, [3]);
+    assert!(seqs(&storage, ROOM, Some(4), Some(2)).await.is_empty());
+    assert!(seqs(&storage, ROOM, Some(6), None).await.is_empty());
+}
+
+async fn empty_room_has_empty_log(storage: impl Storage) {
+    create(&storage, ROOM).await;
+
+    assert!(seqs(&storage, ROOM, None, None).await.is_empty());
+    assert!(storage.list_snapshots(ROOM).await.unwrap().is_empty());
+    assert_eq!(storage.load_snapshot_best(ROOM, None).await.unwrap(), None);
+    assert_eq!(storage.load_snapshot_at(ROOM, 0).await.unwrap(), None);
+
+    let info = storage.get_room_info(ROOM).await.unwrap().unwrap();
+    assert_eq!(info.room_id, ROOM);
+    assert_eq!(info.latest_snapshot, None);
+}
+
+async fn missing_room_is_not_found(storage: impl Storage) {
+    let missing = "missing-room";
+
+    assert!(!storage.room_exists(missing).await.unwrap());
+    assert_eq!(storage.get_room_info(missing).await.unwrap(), None);
+
+    macro_rules! assert_not_found {
+        ($e:expr) => {
+            let res = $e.await;
+            assert!(
+                matches!(res, Err(Error::NotFound)),
+                "{}: {res:?}",
+                stringify!($e)
+            );
+        };
+    }
+
+    assert_not_found!(storage.append_update(missing, b"a", None));
+    assert_not_found!(storage.append_updates(missing, &[update(b"a", None)]));
+    assert_not_found!(storage.load_updates(missing, LoadUpdatesOptions::default()));
+    assert_not_found!(storage.last_seq_at(missing, Utc::now()));
+    assert_not_found!(storage.store_snapshot(missing, snapshot(1, b"s")));
+    assert_not_found!(storage.load_snapshot_at(missing, 1));
+    assert_not_found!(storage.load_snapshot_best(missing, None));
+    assert_not_found!(storage.list_snapshots(missing));
+    assert_not_found!(storage.record_client(missing, 1, "alice"));
+    assert_not_found!(storage.list_clients(missing));
+    assert_not_found!(storage.update_room_metadata(missing, RoomMetadataUpdate::default()));
+    assert_not_found!(storage.claim_room(missing, "alice"));
+    assert_not_found!(storage.create_version(missing, version("draft", 0)));
+    assert_not_found!(storage.list_versions(missing));
+    assert_not_found!(storage.get_version(missing, "draft"));
+    assert_not_found!(storage.delete_version(missing, "draft"));
+    assert_not_found!(storage.set_member(missing, "bob", Role::Editor, None));
+    assert_not_found!(storage.remove_member(missing, "bob"));
+    assert_not_found!(storage.get_member(missing, "bob"));
+    assert_not_found!(storage.list_members(missing));
+    assert_not_found!(storage.create_share(missing, new_share("s", "t", None, None)));
+    assert_not_found!(storage.list_shares(missing));
+    assert_not_found!(storage.delete_share(missing, "s"));
+    assert_not_found!(storage.use_share("no-such-token", Utc::now()));
+    assert_not_found!(storage.prune_updates_through(missing, 1, PruneUpdatesOptions::default()));
+    assert_not_found!(storage.delete_snapshots(missing, &[1]));
+
+    // Deleting is idempotent.
+    storage.delete_room(missing).await.unwrap();
+}
+
+async fn create_room_respects_fail_if_exists(storage: impl Storage) {
+    create(&storage, ROOM).await;
+    append_n(&storage, ROOM, 2).await;
+
+    let res = storage
+        .create_room(
+            ROOM,
+            CreateRoomOptions {
+                fail_if_exists: true,
+                ..Default::default()
+            },
+        )
+        .await;
+    assert!(matches!(res, Err(Error::AlreadyExists)), "{res:?}");
+
+    // Without the flag it is a no-op that keeps the existing log.
+    create(&storage, ROOM).await;
+    assert_eq!(
+        storage.get_room_info(ROOM).await.unwrap().unwrap().last_seq,
+        2
+    );
+    assert!(storage.room_exists(ROOM).await.unwrap());
+}
+
+async fn metadata_is_kept_and_updated(storage: impl Storage) {
+    storage
+        .create_room(
+            ROOM,
+            CreateRoomOptions {
+                title: Some("The Kettle".to_string()),
+                owner: Some("alice".to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+    let created = storage.get_room_info(ROOM).await.unwrap().unwrap();
+    assert_eq!(created.title.as_deref(), Some("The Kettle"));
+    assert_eq!(created.owner.as_deref(), Some("alice"));
+    assert_eq!(created.description, None);
+    assert_eq!(created.updated_at, created.created_at);
+
+    storage
+        .update_room_metadata(
+            ROOM,
+            RoomMetadataUpdate {
+                description: Some("A short about tea.".to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    // Only appends count as edits.
+    let described = storage.get_room_info(ROOM).await.unwrap().unwrap();
+    assert_eq!(described.title.as_deref(), Some("The Kettle"));
+    assert_eq!(described.description.as_deref(), Some("A short about tea."));
+    assert_eq!(described.updated_at, created.updated_at);
+
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    append_n(&storage, ROOM, 1).await;
+    let edited = storage.get_room_info(ROOM).await.unwrap().unwrap();
+    assert!(edited.updated_at > created.updated_at);
+    assert_eq!(edited.created_at, created.created_at);
+
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    storage
+        .append_updates(ROOM, &[update(b"a", None), update(b"b", None)])
+        .await
+        .unwrap();
+    let info = storage.get_room_info(ROOM).await.unwrap().unwrap();
+    assert!(info.updated_at > edited.updated_at);
+}
+
+async fn only_ownerless_rooms_are_claimed(storage: impl Storage) {
+    create(&storage, ROOM).await;
+
+    assert!(storage.claim_room(ROOM, "alice").await.unwrap());
+    assert!(!storage.claim_room(ROOM, "bob").await.unwrap());
+    let info = storage.get_room_info(ROOM).await.unwrap().unwrap();
+    assert_eq!(info.owner.as_deref(), Some("alice"));
+}
+
+async fn list_rooms_is_ordered_by_id(storage: impl Storage) {
+    for room_id in ["conformance-b", "conformance-c", "conformance-a"] {
+        create(&storage, room_id).await;
+    }
+
+    let ids: Vec<String> = storage
+        .list_rooms()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.room_id)
+        .filter(|id| id.starts_with("conformance-"))
+        .collect();
+    assert_eq!(ids, ["conformance-a", "conformance-b", "conformance-c"]);
+}
+
+async fn forks_keep_their_parent(storage: impl Storage) {
+    create(&storage, ROOM).await;
+    append_n(&storage, ROOM, 3).await;
+
+    let fork = ForkPoint {
+        parent: ROOM.to_string(),
+        seq: 2,
+    };
+    storage
+        .create_room(
+            "conformance-fork",
+            CreateRoomOptions {
+                fork: Some(fork.clone()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+    let info = storage.get_room_info("conformance-fork").await.unwrap();
+    assert_eq!(info.unwrap().fork, Some(fork.clone()));
+    assert_eq!(
+        storage.get_room_info(ROOM).await.unwrap().unwrap().fork,
+        None
+    );
+
+    // The fork point outlives the parent.
+    storage.delete_room(ROOM).await.unwrap();
+    let forks: Vec<_> = storage
+        .list_rooms()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter_map(|r| r.fork)
+        .collect();
+    assert_eq!(forks, [fork]);
+}
+
+async fn load_snapshot_best_selects_newest_covered(storage: impl Storage) {
+    create(&storage, ROOM).await;
+    append_n(&storage, ROOM, 5).await;
+    storage
+        .store_snapshot(ROOM, snapshot(4, b"four"))
+        .await
+        .unwrap();
+    storage
+        .store_snapshot(ROOM, snapshot(2, b"two"))
+        .await
+        .unwrap();
+
+    let best = |max| storage.load_snapshot_best(ROOM, max);
+    assert_eq!(best(None).await.unwrap(), Some(snapshot(4, b"four")));
+    assert_eq!(best(Some(5)).await.unwrap(), Some(snapshot(4, b"four")));
+    assert_eq!(best(Some(4)).await.unwrap(), Some(snapshot(4, b"four")));
+    assert_eq!(best(Some(3)).await.unwrap(), Some(snapshot(2, b"two")));
+    assert_eq!(best(Some(1)).await.unwrap(), None);
+
+    assert_eq!(
+        storage.load_snapshot_at(ROOM, 2).await.unwrap(),
+        Some(snapshot(2, b"two"))
+    );
+    assert_eq!(storage.load_snapshot_at(ROOM, 3).await.unwrap(), None);
+
+    let listed: Vec<(LogSeq, u64)> = storage
+        .list_snapshots(ROOM)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|s| (s.covered_through, s.size_bytes))
+        .collect();
+    assert_eq!(listed, [(2, 3), (4, 4)]);
+
+    let latest = storage
+        .get_room_info(ROOM)
+        .await
+        .unwrap()
+        .unwrap()
+        .latest_snapshot
+        .unwrap();
+    assert_eq!((latest.covered_through, latest.size_bytes), (4, 4));
+}
+
+async fn store_snapshot_replaces_duplicate(storage: impl Storage) {
+    create(&storage, ROOM).await;
+    append_n(&storage, ROOM, 2).await;
+
+    storage
+        .store_snapshot(ROOM, snapshot(2, b"old"))
+        .await
+        .unwrap();
+    storage
+        .store_snapshot(ROOM, snapshot(2, b"newer"))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        storage.load_snapshot_at(ROOM, 2).await.unwrap(),
+        Some(snapshot(2, b"newer"))
+    );
+    assert_eq!(storage.list_snapshots(ROOM).await.unwrap().len(), 1);
+}
+
+async fn delete_room_cascades(storage: impl Storage) {
+    create(&storage, ROOM).await;
+    append_n(&storage, ROOM, 3).await;
+    storage
+        .store_snapshot(ROOM, snapshot(3, b"s"))
+        .await
+        .unwrap();
+    storage
+        .create_version(ROOM, version("draft", 3))
+        .await
+        .unwrap();
+    storage
+        .set_member(ROOM, "bob", Role::Viewer, None)
+        .await
+        .unwrap();
+
+    storage.delete_room(ROOM).await.unwrap();
+    assert!(!storage.room_exists(ROOM).await.unwrap());
+    assert_eq!(storage.get_room_info(ROOM).await.unwrap(), None);
+
+    // A new room with the same id starts from scratch.
+    create(&storage, ROOM).await;
+    assert_eq!(
+        storage.get_room_info(ROOM).await.unwrap().unwrap().last_seq,
+        0
+    );
+    assert!(seqs(&storage, ROOM, None, None).await.is_empty());
+    assert!(storage.list_snapshots(ROOM).await.unwrap().is_empty());
+    assert!(storage.list_versions(ROOM).await.unwrap().is_empty());
+    assert!(storage.list_members(ROOM).await.unwrap().is_empty());
+    assert!(storage.list_memberships("bob").await.unwrap().is_empty());
+    assert!(storage.list_shares(ROOM).await.unwrap().is_empty());
+    assert!(matches!(
+        storage.use_share("t", Utc::now()).await,
+        Err(Error::NotFound)
+    ));
+    assert_eq!(storage.append_update(ROOM, b"a", None).await.unwrap(), 1);
+}
+
+async fn last_seq_at_finds_updates_up_to_time(storage: impl Storage) {
+    create(&storage, ROOM).await;
+    let before = Utc::now() - TimeDelta::seconds(1);
+    append_n(&storage, ROOM, 2).await;
+
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    let between = Utc::now();
+    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    append_n(&storage, ROOM, 1).await;
+
+    assert_eq!(storage.last_seq_at(ROOM, before).await.unwrap(), 0);
+    assert_eq!(storage.last_seq_at(ROOM, between).await.unwrap(), 2);
+    assert_eq!(storage.last_seq_at(ROOM, Utc::now()).await.unwrap(), 3);
+}
+
+async fn prune_updates_stops_at_latest_snapshot(storage: impl Storage) {
+    create(&storage, ROOM).await;
+    append_n(&storage, ROOM, 5).await;
+    storage
+        .store_snapshot(ROOM, snapshot(3, b"s"))
+        .await
+        .unwrap();
+
+    // Everything is younger than a day ago, so nothing matches.
+    let opts = PruneUpdatesOptions {
+        created_before: Some(Utc::now() - TimeDelta::days(1)),
+    };
+    assert_eq!(
+        storage.prune_updates_through(ROOM, 5, opts).await.unwrap(),
+        0
+    );
+
+    let pruned = storage
+        .prune_updates_through(ROOM, 5, PruneUpdatesOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(pruned, 3);
+    assert_eq!(seqs(&storage, ROOM, None, None).await, [4, 5]);
+
+    // Pruning never changes the numbering of new updates.
+    assert_eq!(storage.append_update(ROOM, b"f", None).await.unwrap(), 6);
+}
+
+async fn delete_snapshots_reports_removed(storage: impl Storage) {
+    create(&storage, ROOM).await;
+    append_n(&storage, ROOM, 3).await;
+    for ct in 1..=3 {
+        storage
+            .store_snapshot(ROOM, snapshot(ct, b"s"))
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(storage.delete_snapshots(ROOM, &[1, 3, 9]).await.unwrap(), 2);
+
+    let left: Vec<LogSeq> = storage
+        .list_snapshots(ROOM)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|s| s.covered_through)
+        .collect();
+    assert_eq!(left, [2]);
+}
+
+macro_rules! conformance_tests {
+    ($($case:ident),* $(,)?) => {
+        mod in_memory {
+            $(
+                #[tokio::test]
+                async fn $case() {
+                    super::$case(crate::rooms::InMemoryStorage::new().await).await;
+                }
+            )*
+        }
+
+        mod sqlite {
+            $(
+                #[sqlx::test(migrations = "./migrations/sqlite")]
+                async fn $case(pool: sqlx::SqlitePool) {
+                    super::$case(crate::rooms::SqliteStorage::new(pool).await).await;
+                }
+            )*
+        }
+
+        mod postgres {
+            $(
+                #[sqlx::test(migrations = "./migrations")]
+                #[ignore = "needs DATABASE_URL pointing to a Postgres server"]
+                async fn $case(pool: sqlx::PgPool) {
+                    let db = crate::db::Db::new(pool);
+                    super::$case(crate::rooms::DatabaseStorage::new(db).await).await;
+                }
+            )*
+        }
+    };
+}
+
+conformance_tests!(
+    sequence_starts_at_one,
+    authors_are_kept,
+    record_client_keeps_first_user,
+    versions_have_unique_labels,
+    append_updates_rejects_empty,
+    load_updates_ranges_are_inclusive,
+    last_seq_at_finds_updates_up_to_time,
+    empty_room_has_empty_log,
+    missing_room_is_not_found,
+    create_room_respects_fail_if_exists,
+    list_rooms_is_ordered_by_id,
+    forks_keep_their_parent,
+    metadata_is_kept_and_updated,
+    only_ownerless_rooms_are_claimed,
+    members_have_one_role_per_room,
+    shares_are_used_up_and_revoked,
+    load_snapshot_best_selects_newest_covered,
+    store_snapshot_replaces_duplicate,
+    delete_room_cascades,
+    prune_updates_stops_at_latest_snapshot,
+    delete_snapshots_reports_removed,
+);
@@ -0,0 +1,406 @@
+//! Comparing two versions of a script by Fountain element rather than by character.
+//!
+//! Both versions are parsed with rustwell and split into scenes at their headings. Scenes are
+//! matched by heading, a heading used several times matching in order of appearance. Matched
+//! scenes that are not in the longest common ordering of both versions have moved, the rest of
+//! them may still have changed. Dialogue is compared per character within matched scenes.
+use std::collections::{HashMap, HashSet};
+
+use rustwell::screenplay::{Dialogue, DialogueElement, Element};
+use serde::Serialize;
+use similar::{Algorithm, DiffTag, TextDiff, capture_diff_slices};
+
+/// What changed between two versions of a script.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScriptDiff {
+    /// Ordered by position in the new version, removed scenes last.
+    pub scenes: Vec<SceneChange>,
+    /// Ordered by character, then by scene.
+    pub dialogue: Vec<DialogueChange>,
+    /// The whole text as a unified diff.
+    pub unified: String,
+}
+
+/// A scene that differs between the versions.
+///
+/// Positions count scenes from 1. The text before the first heading is a scene without heading.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum SceneChange {
+    Added {
+        heading: Option<String>,
+        to: usize,
+    },
+    Removed {
+        heading: Option<String>,
+        from: usize,
+    },
+    /// Moved relative to the other scenes, `changed` if its content changed too.
+    Moved {
+        heading: Option<String>,
+        from: usize,
+        to: usize,
+        changed: bool,
+    },
+    Changed {
+        heading: Option<String>,
+        from: usize,
+        to: usize,
+    },
+}
+
+/// The speeches of one character in one scene that differ between the versions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DialogueChange {
+    pub character: String,
+    pub scene: Option<String>,
+    pub removed: Vec<String>,
+    pub added: Vec<String>,
+}
+
+struct Scene {
+    heading: Option<String>,
+    elements: Vec<Element>,
+}
+
+/// Compare the Fountain texts `old` and `new`, named `old_name` and `new_name` in the unified diff.
+pub fn diff(old: &str, new: &str, old_name: &str, new_name: &str) -> ScriptDiff {
+    let old_scenes = scenes(old);
+    let new_scenes = scenes(new);
+    let old_keys = keys(&old_scenes);
+    let new_keys = keys(&new_scenes);
+
+    // Index in the old version of each scene, keyed like the new one.
+    let old_index: HashMap<&(Option<String>, usize), usize> =
+        old_keys.iter().enumerate().map(|(i, k)| (k, i)).collect();
+    let matched: HashMap<usize, usize> = new_keys
+        .iter()
+        .enumerate()
+        .filter_map(|(j, k)| old_index.get(k).map(|&i| (j, i)))
+        .collect();
+
+    let moved = moved(new_keys.len(), &matched);
+
+    let mut scenes = Vec::new();
+    let mut dialogue = Vec::new();
+    for (j, scene) in new_scenes.iter().enumerate() {
+        let Some(&i) = matched.get(&j) else {
+            scenes.push(SceneChange::Added {
+                heading: scene.heading.clone(),
+                to: j + 1,
+            });
+            dialogue.extend(dialogue_changes(None, Some(scene)));
+            continue;
+        };
+
+        let changed = old_scenes[i].elements != scene.elements;
+        let heading = scene.heading.clone();
+        if moved.contains(&i) {
+            scenes.push(SceneChange::Moved {
+                heading,
+                from: i + 1,
+                to: j + 1,
+                changed,
+            });
+        } else if changed {
+            scenes.push(SceneChange::Changed {
+                heading,
+                from: i + 1,
+                to: j + 1,
+            });
+        }
+        if changed {
+            dialogue.extend(dialogue_changes(Some(&old_scenes[i]), Some(scene)));
+        }
+    }
+
+    let kept: HashSet<usize> = matched.values().copied().collect();
+    for (i, scene) in old_scenes.iter().enumerate() {
+        if !kept.contains(&i) {
+            scenes.push(SceneChange::Removed {
+                heading: scene.heading.clone(),
+                from: i + 1,
+            });
+            dialogue.extend(dialogue_changes(Some(scene), None));
+        }
+    }
+
+    // Stable, so each character's changes stay in scene order.
+    dialogue.sort_by(|a, b| a.character.cmp(&b.character));
+
+    let unified = TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(3)
+        .header(old_name, new_name)
+        .to_string();
+
+    ScriptDiff {
+        scenes,
+        dialogue,
+        unified,
+    }
+}
+
+/// Split the script at its scene headings.
+fn scenes(text: &str) -> Vec<Scene> {
+    let mut scenes = vec![Scene {
+        heading: None,
+        elements: Vec::new(),
+    }];
+    for span in rustwell::parse(text).elements {
+        match span.inner {
+            Element::Heading { slug, .. } => scenes.push(Scene {
+                heading: Some(slug.to_plain_string()),
+                elements: Vec::new(),
+            }),
+            element => scenes
+                .last_mut()
+                .expect("there is always a scene")
+                .elements
+                .push(element),
+        }
+    }
+
+    if scenes[0].elements.is_empty() {
+        scenes.remove(0);
+    }
+    scenes
+}
+
+/// Identify each scene by its heading and how many scenes before it share the heading.
+fn keys(scenes: &[Scene]) -> Vec<(Option<String>, usize)> {
+    let mut seen: HashMap<&Option<String>, usize> = HashMap::new();
+    scenes
+        .iter()
+        .map(|s| {
+            let n = seen.entry(&s.heading).or_default();
+            *n += 1;
+            (s.heading.clone(), *n)
+        })
+        .collect()
+}
+
+/// The old indices of the matched scenes outside the longest common ordering of both versions.
+///
+/// `matched` maps new indices to old ones.
+fn moved(new_len: usize, matched: &HashMap<usize, usize>) -> HashSet<usize> {
+    let new_order: Vec<usize> = (0..new_len)
+        .filter_map(|j| matched.get(&j).copied())
+        .collect();
+    let mut old_order = new_order.clone();
+    old_order.sort_unstable();
+
+    capture_diff_slices(Algorithm::Myers, &old_order, &new_order)
+        .into_iter()
+        .filter(|op| op.tag() != DiffTag::Equal)
+        .flat_map(|op| old_order[op.old_range()].to_vec())
+        .collect()
+}
+
+/// The speeches of each character in `scene`, in order of first appearance.
+fn speeches(scene: &Scene) -> Vec<(String, Vec<String>)> {
+    let mut by_character: Vec<(String, Vec<String>)> = Vec::new();
+    let mut push = |d: &Dialogue| {
+        let character = d.character.to_plain_string().trim().to_string();
+        let speech = d
+            .elements
+            .iter()
+            .map(|e| match e {
+                DialogueElement::Parenthetical(s) | DialogueElement::Line(s) => s.to_plain_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        match by_character.iter_mut().find(|(c, _)| *c == character) {
+            Some((_, all)) => all.push(speech),
+            None => by_character.push((character, vec![speech])),
+        }
+    };
+
+    for element in &scene.elements {
+        match element {
+            Element::Dialogue(d) => push(d),
+            Element::DualDialogue(a, b) => {
+                push(a);
+                push(b);
+            }
+            _ => {}
+        }
+    }
+    by_character
+}
+
+/// Compare the dialogue of each character between two versions of a scene, either of which
+/// may be missing.
+fn dialogue_changes(old: Option<&Scene>, new: Option<&Scene>) -> Vec<DialogueChange> {
+    let heading = new.or(old).and_then(|s| s.heading.as_ref());
+    let mut old_speeches = old.map(speeches).unwrap_or_default();
+    let mut changes = Vec::new();
+
+    for (character, new_lines) in new.map(speeches).unwrap_or_default() {
+        let old_lines = old_speeches
+            .iter()
+            .position(|(c, _)| *c == character)
+            .map(|i| old_speeches.remove(i).1)
+            .unwrap_or_default();
+        changes.extend(compare(&character, heading, &old_lines, &new_lines));
+    }
+    // Characters who no longer speak in the scene.
+    for (character, old_lines) in old_speeches {
+        changes.extend(compare(&character, heading, &old_lines, &[]));
+    }
+    changes
+}
+
+fn compare(
+    character: &str,
+    scene: Option<&String>,
+    old: &[String],
+    new: &[String],
+) -> Option<DialogueChange> {
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    for op in capture_diff_slices(Algorithm::Myers, old, new) {
+        if op.tag() != DiffTag::Equal {
+            removed.extend_from_slice(&old[op.old_range()]);
+            added.extend_from_slice(&new[op.new_range()]);
+        }
+    }
+
+    if removed.is_empty() && added.is_empty() {
+        return None;
+    }
+    Some(DialogueChange {
+        character: character.to_string(),
+        scene: scene.cloned(),
+        removed,
+        added,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DialogueChange, SceneChange, diff};
+
+    const OLD: &str = "\
+INT. KITCHEN - NIGHT
+
+A kettle whistles.
+
+MARA
+Tea?
+
+DANIEL
+(yawning)
+Coffee.
+
+EXT. GARDEN - DAY
+
+Birds.
+
+INT. HALLWAY - NIGHT
+
+Footsteps.
+";
+
+    const NEW: &str = "\
+EXT. GARDEN - DAY
+
+Birds.
+
+INT. KITCHEN - NIGHT
+
+A kettle whistles.
+
+MARA
+Tea?
+
+DANIEL
+(yawning)
+Water.
+
+INT. ATTIC - NIGHT
+
+MARA
+Who's there?
+";
+
+    fn heading(s: &str) -> Option<String> {
+        Some(s.to_string())
+    }
+
+    #[test]
+    fn scenes_are_added_removed_moved_and_changed() {
+        let d = diff(OLD, NEW, "first draft", "seq 7");
+
+        // Of two swapped scenes, one moved past the other.
+        assert_eq!(
+            d.scenes,
+            [
+                SceneChange::Moved {
+                    heading: heading("EXT. GARDEN - DAY"),
+                    from: 2,
+                    to: 1,
+                    changed: false,
+                },
+                SceneChange::Changed {
+                    heading: heading("INT. KITCHEN - NIGHT"),
+                    from: 1,
+                    to: 2,
+                },
+                SceneChange::Added {
+                    heading: heading("INT. ATTIC - NIGHT"),
+                    to: 3,
+                },
+                SceneChange::Removed {
+                    heading: heading("INT. HALLWAY - NIGHT"),
+                    from: 3,
+                },
+            ]
+        );
+
+        assert!(d.unified.starts_with("--- first draft\n+++ seq 7\n"));
+        assert!(d.unified.contains("\n-Coffee.\n"), "{}", d.unified);
+        assert!(d.unified.contains("\n+Water.\n"), "{}", d.unified);
+    }
+
+    #[test]
+    fn dialogue_changes_are_per_character() {
+        let d = diff(OLD, NEW, "a", "b");
+
+        assert_eq!(
+            d.dialogue,
+            [
+                DialogueChange {
+                    character: "DANIEL".to_string(),
+                    scene: heading("INT. KITCHEN - NIGHT"),
+                    removed: vec!["(yawning)\nCoffee.".to_string()],
+                    added: vec!["(yawning)\nWater.".to_string()],
+                },
+                DialogueChange {
+                    character: "MARA".to_string(),
+                    scene: heading("INT. ATTIC - NIGHT"),
+                    removed: vec![],
+                    added: vec!["Who's there?".to_string()],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn repeated_headings_match_in_order() {
+        let old = "INT. CAR - DAY\n\nRain.\n\nINT. CAR - DAY\n\nSun.\n";
+        let new = "INT. CAR - DAY\n\nRain.\n\nINT. CAR - DAY\n\nSnow.\n";
+
+        let d = diff(old, new, "a", "b");
+        assert_eq!(
+            d.scenes,
+            [SceneChange::Changed {
+                heading: heading("INT. CAR - DAY"),
+                from: 2,
+                to: 2,
+            }]
+        );
+        assert!(diff(old, old, "a", "b").scenes.is_empty());
+    }
+}
@@ -9,6 +9,15 @@ pub enum Error {
     #[error("already exists")]
     AlreadyExists,
 
+    #[error("not allowed")]
+    Forbidden,
+
+    #[error("history has been pruned")]
+    HistoryPruned,
+
+    #[error("shutting down")]
+    ShuttingDown,
+
     #[error("invalid argument: {0}")]
     InvalidArgument(String),
 
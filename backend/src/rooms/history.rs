@@ -0,0 +1,291 @@
+//! Rebuilding a room's document as it was at an earlier point of its log.
+use chrono::{DateTime, Utc};
+use similar::{ChangeTag, TextDiff};
+use yrs::updates::decoder::Decode;
+use yrs::{Doc, GetString, Text, Transact};
+
+use crate::rooms::error::Error;
+use crate::rooms::persistence::authored_by;
+use crate::rooms::storage::{LoadUpdatesOptions, LogSeq, Storage};
+
+/// Name of the shared text holding the Fountain source of a room.
+pub const TEXT: &str = "codemirror";
+
+/// A point in the history of a room.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum At {
+    /// Right after the update with this sequence number was applied. 0 is the empty document.
+    Seq(LogSeq),
+    /// Everything appended at or before this time.
+    Time(DateTime<Utc>),
+}
+
+impl std::fmt::Display for At {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            At::Seq(seq) => write!(f, "seq {seq}"),
+            At::Time(time) => write!(f, "{}", time.to_rfc3339()),
+        }
+    }
+}
+
+/// Rebuild the document of `room_id` at `at` from the best snapshot and the updates after it.
+///
+/// Fails with [`Error::HistoryPruned`] if the updates needed have been removed by retention.
+pub async fn doc_at(storage: &dyn Storage, room_id: &str, at: At) -> Result<Doc, Error> {
+    let info = storage
+        .get_room_info(room_id)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    let seq = match at {
+        At::Seq(seq) if seq > info.last_seq => {
+            return Err(Error::InvalidArgument(format!(
+                "seq {seq} is past the end of the log at {}",
+                info.last_seq
+            )));
+        }
+        At::Seq(seq) => seq,
+        At::Time(time) => seq_at(storage, room_id, time).await?,
+    };
+
+    let doc = Doc::new();
+    let snap = storage.load_snapshot_best(room_id, Some(seq)).await?;
+    let covered = snap.as_ref().map_or(0, |s| s.covered_through);
+
+    let updates = if seq > covered {
+        storage
+            .load_updates(
+                room_id,
+                LoadUpdatesOptions {
+                    from: Some(covered + 1),
+                    to: Some(seq),
+                },
+            )
+            .await?
+    } else {
+        Vec::new()
+    };
+
+    // Seqs are unique, so anything short of the full range means some were pruned.
+    if updates.len() as u64 != seq - covered {
+        return Err(Error::HistoryPruned);
+    }
+
+    {
+        let mut txn = doc.transact_mut();
+        if let Some(s) = snap {
+            txn.apply_update(yrs::Update::decode_v1(&s.bytes)?);
+        }
+        for u in updates {
+            txn.apply_update(yrs::Update::decode_v1(&u.bytes)?);
+        }
+    }
+
+    Ok(doc)
+}
+
+/// The last seq appended at or before `time`.
+///
+/// Snapshots are considered as well, as the updates they cover may have been pruned.
+pub async fn seq_at(
+    storage: &dyn Storage,
+    room_id: &str,
+    time: DateTime<Utc>,
+) -> Result<LogSeq, Error> {
+    let logged = storage.last_seq_at(room_id, time).await?;
+    let snapshotted = storage
+        .list_snapshots(room_id)
+        .await?
+        .into_iter()
+        .filter(|s| s.created_at <= time)
+        .map(|s| s.covered_through)
+        .max()
+        .unwrap_or(0);
+
+    Ok(logged.max(snapshotted))
+}
+
+/// The Fountain source held by `doc`.
+pub fn text(doc: &Doc) -> String {
+    let text = doc.get_or_insert_text(TEXT);
+    text.get_string(&doc.transact())
+}
+
+/// Edit the text of `doc` into `target` in a single transaction on behalf of `author`.
+///
+/// Only the lines that differ are touched, so edits made concurrently to other parts of the
+/// text survive when collaborators merge the change.
+pub fn replace_text(doc: &Doc, target: &str, author: &str) {
+    let text = doc.get_or_insert_text(TEXT);
+    let mut txn = doc.transact_mut_with(authored_by(author));
+    let current = text.get_string(&txn);
+
+    // Offsets are in bytes, the offset kind of a default `Doc`.
+    let mut pos = 0;
+    for change in TextDiff::from_lines(current.as_str(), target).iter_all_changes() {
+        let len = change.value().len() as u32;
+        match change.tag() {
+            ChangeTag::Equal => pos += len,
+            ChangeTag::Delete => text.remove_range(&mut txn, pos, len),
+            ChangeTag::Insert => {
+                text.insert(&mut txn, pos, change.value());
+                pos += len;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use yrs::updates::decoder::Decode;
+    use yrs::{Doc, ReadTxn, Text, Transact};
+
+    use crate::rooms::Error;
+    use crate::rooms::InMemoryStorage;
+    use crate::rooms::history::{self, At};
+    use crate::rooms::storage::{CreateRoomOptions, PruneUpdatesOptions, Snapshot, Storage};
+
+    const ROOM: &str = "room";
+
+    /// Append `chunk` to the text of `doc` and return the update it produced.
+    fn type_text(doc: &Doc, chunk: &str) -> Vec<u8> {
+        let text = doc.get_or_insert_text(history::TEXT);
+        let mut txn = doc.transact_mut();
+        let before = txn.state_vector();
+        text.push(&mut txn, chunk);
+        txn.encode_state_as_update_v1(&before)
+    }
+
+    /// A room with one update per line of a short scene.
+    async fn scene() -> (InMemoryStorage, Doc) {
+        let storage = InMemoryStorage::new().await;
+        storage
+            .create_room(ROOM, CreateRoomOptions::default())
+            .await
+            .unwrap();
+
+        let doc = Doc::new();
+        for line in [
+            "INT. KITCHEN - NIGHT\n",
+            "\nA kettle whistles.\n",
+            "\nMARA\nTea?\n",
+        ] {
+            storage
+                .append_update(ROOM, &type_text(&doc, line), None)
+                .await
+                .unwrap();
+        }
+        (storage, doc)
+    }
+
+    async fn text_at(storage: &InMemoryStorage, at: At) -> Result<String, Error> {
+        Ok(history::text(&history::doc_at(storage, ROOM, at).await?))
+    }
+
+    #[tokio::test]
+    async fn doc_at_seq_replays_through_that_update() {
+        let (storage, _) = scene().await;
+
+        assert_eq!(text_at(&storage, At::Seq(0)).await.unwrap(), "");
+        assert_eq!(
+            text_at(&storage, At::Seq(2)).await.unwrap(),
+            "INT. KITCHEN - NIGHT\n\nA kettle whistles.\n"
+        );
+        assert!(matches!(
+            text_at(&storage, At::Seq(4)).await,
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn doc_at_time_includes_updates_up_to_then() {
+        let (storage, doc) = scene().await;
+
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        let before_edit = Utc::now();
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        storage
+            .append_update(ROOM, &type_text(&doc, "\nDANIEL\nCoffee.\n"), None)
+            .await
+            .unwrap();
+
+        let text = text_at(&storage, At::Time(before_edit)).await.unwrap();
+        assert!(text.ends_with("Tea?\n"), "{text:?}");
+    }
+
+    #[tokio::test]
+    async fn doc_at_uses_snapshots_for_pruned_history() {
+        let (storage, doc) = scene().await;
+
+        let bytes = doc
+            .transact()
+            .encode_state_as_update_v1(&yrs::StateVector::default());
+        storage
+            .store_snapshot(
+                ROOM,
+                Snapshot {
+                    covered_through: 3,
+                    bytes,
+                },
+            )
+            .await
+            .unwrap();
+        storage
+            .prune_updates_through(ROOM, 3, PruneUpdatesOptions::default())
+            .await
+            .unwrap();
+
+        assert!(
+            text_at(&storage, At::Seq(3))
+                .await
+                .unwrap()
+                .ends_with("Tea?\n")
+        );
+        assert!(text_at(&storage, At::Time(Utc::now())).await.is_ok());
+        assert!(matches!(
+            text_at(&storage, At::Seq(1)).await,
+            Err(Error::HistoryPruned)
+        ));
+    }
+
+    #[tokio::test]
+    async fn replace_text_converges_with_concurrent_edits() {
+        let (storage, doc) = scene().await;
+        let target = text_at(&storage, At::Seq(1)).await.unwrap();
+        // Byte offsets must hold up with non-ASCII text.
+        type_text(&doc, "\nJOSÉ\n¿Té?\n");
+
+        let other = Doc::new();
+        let state = doc
+            .transact()
+            .encode_state_as_update_v1(&yrs::StateVector::default());
+        other
+            .transact_mut()
+            .apply_update(yrs::Update::decode_v1(&state).unwrap());
+
+        // A collaborator adds a line while the document is being restored.
+        let concurrent = {
+            let text = other.get_or_insert_text(history::TEXT);
+            let mut txn = other.transact_mut();
+            let before = txn.state_vector();
+            text.insert(&mut txn, 0, "FADE IN:\n\n");
+            txn.encode_state_as_update_v1(&before)
+        };
+
+        let before = doc.transact().state_vector();
+        history::replace_text(&doc, &target, "mara");
+        assert_eq!(history::text(&doc), target);
+        let restore = doc.transact().encode_state_as_update_v1(&before);
+
+        doc.transact_mut()
+            .apply_update(yrs::Update::decode_v1(&concurrent).unwrap());
+        other
+            .transact_mut()
+            .apply_update(yrs::Update::decode_v1(&restore).unwrap());
+
+        assert_eq!(history::text(&doc), "FADE IN:\n\nINT. KITCHEN - NIGHT\n");
+        assert_eq!(history::text(&doc), history::text(&other));
+    }
+}
@@ -1,12 +1,14 @@
 use crate::rooms::Error;
 use crate::rooms::storage::{
-    CreateRoomOptions, LoadUpdatesOptions, LogSeq, RoomInfo, Snapshot, SnapshotInfo, Storage,
-    UpdateEntry,
+    ClientInfo, CreateRoomOptions, LoadUpdatesOptions, LogSeq, Member, NewShare, NewUpdate,
+    NewVersion, PruneUpdatesOptions, Role, RoomInfo, RoomMetadataUpdate, Share, Snapshot,
+    SnapshotInfo, Storage, UpdateEntry, Version,
 };
 
 use std::collections::{BTreeMap, HashMap};
 
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 
 use tokio::sync::RwLock;
 
@@ -18,8 +20,43 @@ pub struct InMemoryStorage {
 
 pub struct RoomData {
     info: RoomInfo,
-    updates: Vec<(LogSeq, Vec<u8>)>,
-    snapshots: BTreeMap<LogSeq, Vec<u8>>,
+    updates: Vec<StoredUpdate>,
+    snapshots: BTreeMap<LogSeq, StoredSnapshot>,
+    clients: BTreeMap<u64, ClientInfo>,
+    versions: HashMap<String, Version>,
+    members: BTreeMap<String, Member>,
+    shares: BTreeMap<String, Share>,
+}
+
+struct StoredUpdate {
+    seq: LogSeq,
+    bytes: Vec<u8>,
+    author: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+struct StoredSnapshot {
+    bytes: Vec<u8>,
+    created_at: DateTime<Utc>,
+}
+
+impl RoomData {
+    fn info(&self) -> RoomInfo {
+        RoomInfo {
+            latest_snapshot: self.snapshots.iter().next_back().map(|(k, v)| v.info(*k)),
+            ..self.info.clone()
+        }
+    }
+}
+
+impl StoredSnapshot {
+    fn info(&self, covered_through: LogSeq) -> SnapshotInfo {
+        SnapshotInfo {
+            covered_through,
+            size_bytes: self.bytes.len().try_into().expect("Should fit in u64"),
+            created_at: self.created_at,
+        }
+    }
 }
 
 impl InMemoryStorage {
@@ -28,6 +65,7 @@ impl InMemoryStorage {
             rooms: RwLock::new(HashMap::new()),
         };
 
+        // Without an owner, the demo room is claimed by the first user to open it.
         storage
             .create_room(
                 "demo-room-1",
@@ -43,6 +81,7 @@ impl InMemoryStorage {
                 &demo_doc()
                     .transact()
                     .encode_state_as_update_v1(&yrs::StateVector::default()),
+                None,
             )
             .await
             .expect("Initialization calls should work");
@@ -70,11 +109,13 @@ impl Storage for InMemoryStorage {
         Ok(room
             .updates
             .iter()
-            .skip_while(|(seq, _)| *seq < from)
-            .take_while(|(seq, _)| *seq <= to)
-            .map(|(seq, bytes)| UpdateEntry {
-                seq: *seq,
-                bytes: bytes.clone(),
+            .skip_while(|u| u.seq < from)
+            .take_while(|u| u.seq <= to)
+            .map(|u| UpdateEntry {
+                seq: u.seq,
+                bytes: u.bytes.clone(),
+                author: u.author.clone(),
+                created_at: u.created_at,
             })
             .collect())
     }
@@ -90,6 +131,7 @@ impl Storage for InMemoryStorage {
             return Ok(());
         }
 
+        let now = Utc::now();
         rooms.insert(
             room_id.to_string(),
             RoomData {
@@ -97,15 +139,56 @@ impl Storage for InMemoryStorage {
                     room_id: room_id.to_string(),
                     last_seq: 0,
                     latest_snapshot: None,
+                    fork: opts.fork,
+                    title: opts.title,
+                    owner: opts.owner,
+                    description: opts.description,
+                    created_at: now,
+                    updated_at: now,
                 },
                 updates: Vec::new(),
                 snapshots: BTreeMap::new(),
+                clients: BTreeMap::new(),
+                versions: HashMap::new(),
+                members: BTreeMap::new(),
+                shares: BTreeMap::new(),
             },
         );
 
         Ok(())
     }
 
+    async fn update_room_metadata(
+        &self,
+        room_id: &str,
+        update: RoomMetadataUpdate,
+    ) -> Result<(), Error> {
+        let mut rooms = self.rooms.write().await;
+        let info = &mut rooms.get_mut(room_id).ok_or(Error::NotFound)?.info;
+
+        if let Some(title) = update.title {
+            info.title = Some(title);
+        }
+        if let Some(owner) = update.owner {
+            info.owner = Some(owner);
+        }
+        if let Some(description) = update.description {
+            info.description = Some(description);
+        }
+        Ok(())
+    }
+
+    async fn claim_room(&self, room_id: &str, owner: &str) -> Result<bool, Error> {
+        let mut rooms = self.rooms.write().await;
+        let info = &mut rooms.get_mut(room_id).ok_or(Error::NotFound)?.info;
+
+        if info.owner.is_some() {
+            return Ok(false);
+        }
+        info.owner = Some(owner.to_string());
+        Ok(true)
+    }
+
     async fn delete_room(&self, room_id: &str) -> Result<(), Error> {
         let mut rooms = self.rooms.write().await;
 
@@ -115,28 +198,39 @@ impl Storage for InMemoryStorage {
     }
 
     async fn list_rooms(&self) -> Result<Vec<RoomInfo>, Error> {
-        let rooms = self.rooms.write().await;
+        let rooms = self.rooms.read().await;
 
-        Ok(rooms
-            .values()
-            .map(|room_data| room_data.info.clone())
-            .collect())
+        let mut infos: Vec<RoomInfo> = rooms.values().map(RoomData::info).collect();
+        infos.sort_by(|a, b| a.room_id.cmp(&b.room_id));
+        Ok(infos)
     }
 
     async fn get_room_info(&self, room_id: &str) -> Result<Option<RoomInfo>, Error> {
-        let rooms = self.rooms.write().await;
-        Ok(rooms.get(room_id).map(|x| x.info.clone()))
+        let rooms = self.rooms.read().await;
+        Ok(rooms.get(room_id).map(RoomData::info))
     }
 
-    async fn append_update(&self, room_id: &str, update: &[u8]) -> Result<LogSeq, Error> {
+    async fn append_update(
+        &self,
+        room_id: &str,
+        update: &[u8],
+        author: Option<&str>,
+    ) -> Result<LogSeq, Error> {
         let mut rooms = self.rooms.write().await;
         let room = rooms.get_mut(room_id).ok_or(Error::NotFound)?;
 
         let new_seq = room.info.last_seq + 1;
+        let now = Utc::now();
 
-        room.updates.push((new_seq, update.to_vec()));
+        room.updates.push(StoredUpdate {
+            seq: new_seq,
+            bytes: update.to_vec(),
+            author: author.map(str::to_string),
+            created_at: now,
+        });
 
         room.info.last_seq = new_seq;
+        room.info.updated_at = now;
 
         Ok(new_seq)
     }
@@ -144,28 +238,74 @@ impl Storage for InMemoryStorage {
     async fn append_updates(
         &self,
         room_id: &str,
-        updates: &[Vec<u8>],
+        updates: &[NewUpdate],
     ) -> Result<(LogSeq, LogSeq), Error> {
-        let first = self.append_update(room_id, &updates[0]).await?;
+        let Some((head, tail)) = updates.split_first() else {
+            return Err(Error::InvalidArgument(
+                "updates must be non-empty".to_string(),
+            ));
+        };
+
+        let first = self
+            .append_update(room_id, &head.bytes, head.author.as_deref())
+            .await?;
 
         let mut last = first;
-        for update in &updates[1..] {
-            last = self.append_update(room_id, update).await?;
+        for update in tail {
+            last = self
+                .append_update(room_id, &update.bytes, update.author.as_deref())
+                .await?;
         }
 
         Ok((first, last))
     }
 
-    async fn store_snapshot(&self, room_id: &str, snapshot: Snapshot) -> Result<(), Error> {
+    async fn last_seq_at(&self, room_id: &str, at: DateTime<Utc>) -> Result<LogSeq, Error> {
+        let rooms = self.rooms.read().await;
+        let room = rooms.get(room_id).ok_or(Error::NotFound)?;
+
+        Ok(room
+            .updates
+            .iter()
+            .filter(|u| u.created_at <= at)
+            .map(|u| u.seq)
+            .max()
+            .unwrap_or(0))
+    }
+
+    async fn prune_updates_through(
+        &self,
+        room_id: &str,
+        through: LogSeq,
+        opts: PruneUpdatesOptions,
+    ) -> Result<u64, Error> {
         let mut rooms = self.rooms.write().await;
         let room = rooms.get_mut(room_id).ok_or(Error::NotFound)?;
 
-        if room.snapshots.contains_key(&snapshot.covered_through) {
-            return Ok(());
-        }
+        let covered = room.snapshots.keys().next_back().copied().unwrap_or(0);
+        let through = through.min(covered);
+
+        let before = room.updates.len();
+        room.updates
+            .retain(|u| u.seq > through || opts.created_before.is_some_and(|t| u.created_at >= t));
 
-        room.snapshots
-            .insert(snapshot.covered_through, snapshot.bytes);
+        Ok((before - room.updates.len())
+            .try_into()
+            .expect("Should fit in u64"))
+    }
+
+    async fn store_snapshot(&self, room_id: &str, snapshot: Snapshot) -> Result<(), Error> {
+        let mut rooms = self.rooms.write().await;
+        let room = rooms.get_mut(room_id).ok_or(Error::NotFound)?;
+
+        // Upsert snapshot by covered_through.
+        room.snapshots.insert(
+            snapshot.covered_through,
+            StoredSnapshot {
+                bytes: snapshot.bytes,
+                created_at: Utc::now(),
+            },
+        );
 
         Ok(())
     }
@@ -178,9 +318,9 @@ impl Storage for InMemoryStorage {
         let rooms = self.rooms.read().await;
         let room = rooms.get(room_id).ok_or(Error::NotFound)?;
 
-        Ok(room.snapshots.get(&covered_through).map(|bytes| Snapshot {
+        Ok(room.snapshots.get(&covered_through).map(|s| Snapshot {
             covered_through,
-            bytes: bytes.clone(),
+            bytes: s.bytes.clone(),
         }))
     }
 
@@ -199,7 +339,7 @@ impl Storage for InMemoryStorage {
 
         Ok(snapshot.map(|(k, v)| Snapshot {
             covered_through: *k,
-            bytes: v.clone(),
+            bytes: v.bytes.clone(),
         }))
     }
 
@@ -207,15 +347,226 @@ impl Storage for InMemoryStorage {
         let rooms = self.rooms.read().await;
         let room = rooms.get(room_id).ok_or(Error::NotFound)?;
 
-        Ok(room
-            .snapshots
+        Ok(room.snapshots.iter().map(|(k, v)| v.info(*k)).collect())
+    }
+
+    async fn delete_snapshots(
+        &self,
+        room_id: &str,
+        covered_through: &[LogSeq],
+    ) -> Result<u64, Error> {
+        let mut rooms = self.rooms.write().await;
+        let room = rooms.get_mut(room_id).ok_or(Error::NotFound)?;
+
+        Ok(covered_through
+            .iter()
+            .filter(|seq| room.snapshots.remove(seq).is_some())
+            .count()
+            .try_into()
+            .expect("Should fit in u64"))
+    }
+
+    async fn record_client(
+        &self,
+        room_id: &str,
+        client_id: u64,
+        user_id: &str,
+    ) -> Result<bool, Error> {
+        let mut rooms = self.rooms.write().await;
+        let room = rooms.get_mut(room_id).ok_or(Error::NotFound)?;
+
+        let client = room.clients.entry(client_id).or_insert_with(|| ClientInfo {
+            client_id,
+            user_id: user_id.to_string(),
+            first_seen: Utc::now(),
+        });
+
+        Ok(client.user_id == user_id)
+    }
+
+    async fn list_clients(&self, room_id: &str) -> Result<Vec<ClientInfo>, Error> {
+        let rooms = self.rooms.read().await;
+        let room = rooms.get(room_id).ok_or(Error::NotFound)?;
+
+        Ok(room.clients.values().cloned().collect())
+    }
+
+    async fn create_version(&self, room_id: &str, version: NewVersion) -> Result<Version, Error> {
+        let mut rooms = self.rooms.write().await;
+        let room = rooms.get_mut(room_id).ok_or(Error::NotFound)?;
+
+        if room.versions.contains_key(&version.label) {
+            return Err(Error::AlreadyExists);
+        }
+
+        let version = Version {
+            label: version.label,
+            seq: version.seq,
+            author: version.author,
+            note: version.note,
+            created_at: Utc::now(),
+        };
+        room.versions.insert(version.label.clone(), version.clone());
+
+        Ok(version)
+    }
+
+    async fn list_versions(&self, room_id: &str) -> Result<Vec<Version>, Error> {
+        let rooms = self.rooms.read().await;
+        let room = rooms.get(room_id).ok_or(Error::NotFound)?;
+
+        let mut versions: Vec<Version> = room.versions.values().cloned().collect();
+        versions.sort_by_key(|v| (v.seq, v.created_at));
+        Ok(versions)
+    }
+
+    async fn get_version(&self, room_id: &str, label: &str) -> Result<Option<Version>, Error> {
+        let rooms = self.rooms.read().await;
+        let room = rooms.get(room_id).ok_or(Error::NotFound)?;
+
+        Ok(room.versions.get(label).cloned())
+    }
+
+    async fn delete_version(&self, room_id: &str, label: &str) -> Result<(), Error> {
+        let mut rooms = self.rooms.write().await;
+        let room = rooms.get_mut(room_id).ok_or(Error::NotFound)?;
+
+        room.versions
+            .remove(label)
+            .map(|_| ())
+            .ok_or(Error::NotFound)
+    }
+
+    async fn set_member(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        role: Role,
+        share_id: Option<&str>,
+    ) -> Result<Member, Error> {
+        let mut rooms = self.rooms.write().await;
+        let room = rooms.get_mut(room_id).ok_or(Error::NotFound)?;
+
+        if let Some(share_id) = share_id
+            && !room.shares.contains_key(share_id)
+        {
+            return Err(Error::NotFound);
+        }
+
+        let member = Member {
+            user_id: user_id.to_string(),
+            role,
+            share_id: share_id.map(str::to_string),
+            granted_at: Utc::now(),
+        };
+        room.members.insert(user_id.to_string(), member.clone());
+
+        Ok(member)
+    }
+
+    async fn remove_member(&self, room_id: &str, user_id: &str) -> Result<(), Error> {
+        let mut rooms = self.rooms.write().await;
+        let room = rooms.get_mut(room_id).ok_or(Error::NotFound)?;
+
+        room.members
+            .remove(user_id)
+            .map(|_| ())
+            .ok_or(Error::NotFound)
+    }
+
+    async fn get_member(&self, room_id: &str, user_id: &str) -> Result<Option<Member>, Error> {
+        let rooms = self.rooms.read().await;
+        let room = rooms.get(room_id).ok_or(Error::NotFound)?;
+
+        Ok(room.members.get(user_id).cloned())
+    }
+
+    async fn list_members(&self, room_id: &str) -> Result<Vec<Member>, Error> {
+        let rooms = self.rooms.read().await;
+        let room = rooms.get(room_id).ok_or(Error::NotFound)?;
+
+        Ok(room.members.values().cloned().collect())
+    }
+
+    async fn list_memberships(&self, user_id: &str) -> Result<Vec<(String, Role)>, Error> {
+        let rooms = self.rooms.read().await;
+
+        Ok(rooms
             .iter()
-            .map(|(k, v)| SnapshotInfo {
-                covered_through: *k,
-                size_bytes: v.len().try_into().expect("Should fit in u64"),
+            .filter_map(|(room_id, room)| {
+                room.members
+                    .get(user_id)
+                    .map(|member| (room_id.clone(), member.role))
             })
             .collect())
     }
+
+    async fn create_share(&self, room_id: &str, share: NewShare) -> Result<Share, Error> {
+        let mut rooms = self.rooms.write().await;
+        if !rooms.contains_key(room_id) {
+            return Err(Error::NotFound);
+        }
+        let taken = rooms
+            .values()
+            .flat_map(|r| r.shares.values())
+            .any(|s| s.share_id == share.share_id || s.token == share.token);
+        if taken {
+            return Err(Error::AlreadyExists);
+        }
+
+        let share = Share {
+            share_id: share.share_id,
+            room_id: room_id.to_string(),
+            token: share.token,
+            role: share.role,
+            created_by: share.created_by,
+            expires_at: share.expires_at,
+            max_uses: share.max_uses,
+            uses: 0,
+            created_at: Utc::now(),
+        };
+        let room = rooms.get_mut(room_id).expect("Checked above");
+        room.shares.insert(share.share_id.clone(), share.clone());
+
+        Ok(share)
+    }
+
+    async fn list_shares(&self, room_id: &str) -> Result<Vec<Share>, Error> {
+        let rooms = self.rooms.read().await;
+        let room = rooms.get(room_id).ok_or(Error::NotFound)?;
+
+        let mut shares: Vec<Share> = room.shares.values().cloned().collect();
+        shares.sort_by_key(|s| s.created_at);
+        Ok(shares)
+    }
+
+    async fn delete_share(&self, room_id: &str, share_id: &str) -> Result<(), Error> {
+        let mut rooms = self.rooms.write().await;
+        let room = rooms.get_mut(room_id).ok_or(Error::NotFound)?;
+
+        room.shares.remove(share_id).ok_or(Error::NotFound)?;
+        room.members
+            .retain(|_, m| m.share_id.as_deref() != Some(share_id));
+        Ok(())
+    }
+
+    async fn use_share(&self, token: &str, now: DateTime<Utc>) -> Result<Share, Error> {
+        let mut rooms = self.rooms.write().await;
+
+        let share = rooms
+            .values_mut()
+            .flat_map(|r| r.shares.values_mut())
+            .find(|s| s.token == token)
+            .ok_or(Error::NotFound)?;
+        let expired = share.expires_at.is_some_and(|at| at <= now);
+        let used_up = share.max_uses.is_some_and(|max| share.uses >= max);
+        if expired || used_up {
+            return Err(Error::NotFound);
+        }
+
+        share.uses += 1;
+        Ok(share.clone())
+    }
 }
 
 fn demo_doc() -> Doc {
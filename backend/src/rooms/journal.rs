@@ -0,0 +1,183 @@
+//! Local on-disk journal of updates that could not be written to [`Storage`](super::Storage).
+//!
+//! Every room has its own file of length prefixed updates in the configured directory. Each entry
+//! holds the length prefixed author, empty if there is none, followed by the update. Entries are
+//! only ever appended and the whole file is removed once it has been replayed.
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+use crate::rooms::error::Error;
+use crate::rooms::storage::NewUpdate;
+
+const LEN_PREFIX: usize = size_of::<u32>();
+
+pub struct Journal {
+    path: PathBuf,
+    max_bytes: u64,
+    size: u64,
+}
+
+impl Journal {
+    /// Open the journal of `room_id` in `dir`, picking up entries left by an earlier run.
+    pub async fn open(dir: &Path, room_id: &str, max_bytes: u64) -> io::Result<Self> {
+        fs::create_dir_all(dir).await?;
+
+        // Room ids are user supplied, hex keeps them a valid and unique file name.
+        let name: String = room_id.bytes().map(|b| format!("{b:02x}")).collect();
+        let path = dir.join(format!("{name}.journal"));
+
+        let size = match fs::read(&path).await {
+            Ok(buf) => {
+                let (_, valid) = parse(&buf);
+                // A crash during `append` can leave a partial entry. Cut it off so entries
+                // appended from now on stay readable. Clients still holding that update send it
+                // again when they resync with the room.
+                if valid < buf.len() {
+                    tracing::warn!(
+                        ?path,
+                        bytes = buf.len() - valid,
+                        "dropping truncated journal entry"
+                    );
+                    OpenOptions::new()
+                        .write(true)
+                        .open(&path)
+                        .await?
+                        .set_len(valid as u64)
+                        .await?;
+                }
+                valid as u64
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self {
+            path,
+            max_bytes,
+            size,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Size of the journal in bytes.
+    pub fn len(&self) -> u64 {
+        self.size
+    }
+
+    /// Durably append `entries`, failing with [`io::ErrorKind::StorageFull`] if the journal
+    /// would grow beyond its limit.
+    pub async fn append(&mut self, entries: &[NewUpdate]) -> io::Result<()> {
+        let mut buf = Vec::new();
+        for entry in entries {
+            let author = entry.author.as_deref().unwrap_or_default().as_bytes();
+            let len = author.len() + LEN_PREFIX + entry.bytes.len();
+            put_len(&mut buf, len)?;
+            put_len(&mut buf, author.len())?;
+            buf.extend_from_slice(author);
+            buf.extend_from_slice(&entry.bytes);
+        }
+
+        if self.size + buf.len() as u64 > self.max_bytes {
+            return Err(io::Error::new(
+                io::ErrorKind::StorageFull,
+                "journal size limit reached",
+            ));
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        if let Err(e) = write_durably(&mut file, &buf).await {
+            // Cut off a partial write so later entries stay readable.
+            let _ = file.set_len(self.size).await;
+            return Err(e);
+        }
+
+        self.size += buf.len() as u64;
+        Ok(())
+    }
+
+    /// Read every entry in the order it was appended.
+    pub async fn read_all(&self) -> io::Result<Vec<NewUpdate>> {
+        if self.is_empty() {
+            return Ok(Vec::new());
+        }
+        let buf = fs::read(&self.path).await?;
+        parse(&buf).0.into_iter().map(decode).collect()
+    }
+
+    /// Remove every entry.
+    pub async fn clear(&mut self) -> io::Result<()> {
+        match fs::remove_file(&self.path).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Split `buf` into entries, returning them and the length of the complete entries.
+fn parse(buf: &[u8]) -> (Vec<Vec<u8>>, usize) {
+    let mut entries = Vec::new();
+    let mut rest = buf;
+    while rest.len() >= LEN_PREFIX {
+        let (len, tail) = rest.split_at(LEN_PREFIX);
+        let len = u32::from_le_bytes(len.try_into().expect("prefix is 4 bytes")) as usize;
+        if tail.len() < len {
+            break;
+        }
+        let (entry, tail) = tail.split_at(len);
+        entries.push(entry.to_vec());
+        rest = tail;
+    }
+    (entries, buf.len() - rest.len())
+}
+
+fn put_len(buf: &mut Vec<u8>, len: usize) -> io::Result<()> {
+    let len = u32::try_from(len)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "entry too large"))?;
+    buf.extend_from_slice(&len.to_le_bytes());
+    Ok(())
+}
+
+/// Split an entry into its author and update.
+fn decode(entry: Vec<u8>) -> io::Result<NewUpdate> {
+    let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+    let (len, rest) = entry
+        .split_at_checked(LEN_PREFIX)
+        .ok_or_else(|| invalid("entry too short"))?;
+    let len = u32::from_le_bytes(len.try_into().expect("prefix is 4 bytes")) as usize;
+    let (author, bytes) = rest
+        .split_at_checked(len)
+        .ok_or_else(|| invalid("author exceeds entry"))?;
+    let author = String::from_utf8(author.to_vec()).map_err(|_| invalid("author is not utf-8"))?;
+
+    Ok(NewUpdate {
+        bytes: bytes.to_vec(),
+        author: (!author.is_empty()).then_some(author),
+    })
+}
+
+async fn write_durably(file: &mut fs::File, buf: &[u8]) -> io::Result<()> {
+    file.write_all(buf).await?;
+    file.sync_data().await
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Backend {
+            source: Box::new(e),
+        }
+    }
+}
@@ -1,26 +1,61 @@
+use std::ops::Deref;
 use std::sync::atomic::AtomicUsize;
 use std::{collections::HashMap, sync::Arc};
 
-use tokio::sync::{RwLock, mpsc};
+use chrono::{DateTime, Utc};
+use futures_util::future::join_all;
+use rand::{Rng, distr::Alphanumeric};
+use tokio::sync::{RwLock, watch};
+use tokio::time::{Duration, Instant};
+use yrs::block::ClientID;
 use yrs::sync::Awareness;
 use yrs::updates::decoder::Decode;
 use yrs::{Doc, ReadTxn, Subscription, Transact};
 use yrs_axum::{AwarenessRef, broadcast::BroadcastGroup};
 
+use crate::config::Eviction;
+use crate::rooms::blame::{self, BlameLine};
+use crate::rooms::diff::{self, ScriptDiff};
 use crate::rooms::error::Error;
-use crate::rooms::storage::{self, LoadUpdatesOptions, Storage};
+use crate::rooms::history::{self, At};
+use crate::rooms::journal::Journal;
+use crate::rooms::merge::{self, Merge};
+use crate::rooms::persistence::{self, PersistenceMetrics, PersistenceSettings, PersistenceStatus};
+use crate::rooms::storage::{
+    self, ForkPoint, LoadUpdatesOptions, LogSeq, Member, NewShare, NewVersion, Role, RoomInfo,
+    RoomMetadataUpdate, Share, Storage, Version,
+};
 
 pub struct LiveRoom {
     pub bcast: Arc<BroadcastGroup>,
     pub awareness: AwarenessRef,
+    pub persistence: Arc<PersistenceStatus>,
     _sub: Subscription,
     conn_count: AtomicUsize,
+    /// When the last connection closed, while there are none.
+    idle_since: std::sync::Mutex<Option<Instant>>,
+    /// Set once the room is deleted and its connections should close.
+    deleted: watch::Sender<bool>,
+    /// Notified whenever roles in the room change, so its connections check theirs again.
+    access: watch::Sender<()>,
+}
+
+/// Why a connection to a room should close.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    ShuttingDown,
+    Deleted,
+    /// The user may no longer view the room.
+    Revoked,
+    /// The user has another role than the connection was opened with.
+    RoleChanged,
 }
 
 impl LiveRoom {
     fn inc(&self) {
         self.conn_count
             .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        *self.idle_since.lock().unwrap() = None;
     }
 
     fn dec(&self) -> usize {
@@ -28,109 +63,892 @@ impl LiveRoom {
             .fetch_sub(1, std::sync::atomic::Ordering::Relaxed)
             - 1
     }
+
+    fn idle_since(&self) -> Option<Instant> {
+        *self.idle_since.lock().unwrap()
+    }
+}
+
+/// A connection to a [`LiveRoom`]. The room counts it until the lease is released or dropped.
+pub struct RoomLease {
+    rooms: RoomManager,
+    room_id: String,
+    room: Option<Arc<LiveRoom>>,
+}
+
+impl RoomLease {
+    pub fn room_id(&self) -> &str {
+        &self.room_id
+    }
+
+    /// Release the connection. If it was the last one and the room is evicted right away, this
+    /// waits until its updates are written out.
+    pub async fn release(mut self) {
+        let room = self.room.take().expect("lease is held until released");
+        if room.dec() == 0 {
+            drop(room);
+            self.rooms.idle(&self.room_id).await;
+        }
+    }
+
+    /// Resolves once the connection of `user_id`, opened with `role`, should be closed: the
+    /// server is shutting down, the room is deleted or the user's role in it changed.
+    pub async fn close_requested(&self, user_id: &str, role: Role) -> CloseReason {
+        let mut shutdown = self.rooms.shutdown.subscribe();
+        let mut deleted = self.deleted.subscribe();
+        let mut access = self.access.subscribe();
+        // The role may have changed since the connection was authorized, before it listened.
+        access.mark_changed();
+        let role_changed = async {
+            while access.changed().await.is_ok() {
+                match self.rooms.role(&self.room_id, user_id).await {
+                    Ok(Some(now)) if now == role => {}
+                    Ok(Some(_)) => return CloseReason::RoleChanged,
+                    // A deleted room has no roles left either.
+                    Ok(None) | Err(Error::NotFound) if *self.deleted.borrow() => {
+                        return CloseReason::Deleted;
+                    }
+                    Ok(None) | Err(Error::NotFound) => return CloseReason::Revoked,
+                    // The role is checked again when the client reconnects.
+                    Err(e) => {
+                        tracing::warn!(room_id = self.room_id, user_id, error = ?e, "checking role failed");
+                        return CloseReason::RoleChanged;
+                    }
+                }
+            }
+            std::future::pending().await
+        };
+        // The senders live in the manager and the room, so neither can fail.
+        tokio::select! {
+            _ = shutdown.wait_for(|shutdown| *shutdown) => CloseReason::ShuttingDown,
+            _ = deleted.wait_for(|deleted| *deleted) => CloseReason::Deleted,
+            reason = role_changed => reason,
+        }
+    }
+
+    /// Record that the yrs client `client_id` editing the room belongs to `user_id`. Returns false
+    /// if it was recorded for another user before.
+    pub async fn record_client(&self, client_id: ClientID, user_id: &str) -> Result<bool, Error> {
+        self.rooms
+            .storage
+            .record_client(&self.room_id, client_id, user_id)
+            .await
+    }
+
+    #[cfg(test)]
+    pub fn downgrade(&self) -> std::sync::Weak<LiveRoom> {
+        Arc::downgrade(self.room.as_ref().expect("lease is held until released"))
+    }
+}
+
+impl Deref for RoomLease {
+    type Target = LiveRoom;
+
+    fn deref(&self) -> &LiveRoom {
+        self.room.as_ref().expect("lease is held until released")
+    }
+}
+
+impl Drop for RoomLease {
+    /// Release a lease that was not released explicitly, e.g. because the upgrade never completed
+    /// or the connection was aborted.
+    fn drop(&mut self) {
+        let Some(room) = self.room.take() else {
+            return;
+        };
+        if room.dec() > 0 {
+            return;
+        }
+        drop(room);
+
+        // Without a runtime the process is shutting down and there is nothing left to evict to.
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            let rooms = self.rooms.clone();
+            let room_id = std::mem::take(&mut self.room_id);
+            handle.spawn(async move { rooms.idle(&room_id).await });
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct RoomManager {
     storage: Arc<dyn Storage>,
     live: Arc<RwLock<HashMap<String, Arc<LiveRoom>>>>,
+    /// Rooms evicted from `live` whose persistence has not finished yet.
+    evicting: Arc<RwLock<HashMap<String, Arc<PersistenceStatus>>>>,
     bcast_capacity: usize,
-    snapshot_every_n_updates: u64,
-    persist_queue_capacity: usize,
+    persistence: PersistenceSettings,
+    eviction: Eviction,
+    metrics: Arc<PersistenceMetrics>,
+    /// Set once [`RoomManager::shutdown`] has been called.
+    shutdown: Arc<watch::Sender<bool>>,
 }
 
 impl RoomManager {
     pub fn new(
         storage: Arc<dyn Storage>,
         bcast_capacity: usize,
-        snapshot_every_n_updates: u64,
-        persist_queue_capacity: usize,
+        persistence: PersistenceSettings,
+        eviction: Eviction,
     ) -> Self {
         Self {
             storage,
             live: Arc::new(RwLock::new(HashMap::new())),
+            evicting: Arc::new(RwLock::new(HashMap::new())),
             bcast_capacity,
-            snapshot_every_n_updates,
-            persist_queue_capacity,
+            persistence,
+            eviction,
+            metrics: Arc::new(PersistenceMetrics::default()),
+            shutdown: Arc::new(watch::Sender::new(false)),
         }
     }
 
-    /// Aquire the [`LiveRoom`] for the `room_id` or attempt to cretate it
+    /// Persistence counters for every room handled by this manager.
+    pub fn persistence_metrics(&self) -> &PersistenceMetrics {
+        &self.metrics
+    }
+
+    /// Ids of the live rooms holding updates that have not reached storage, sorted.
+    pub async fn unhealthy_rooms(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self
+            .live
+            .read()
+            .await
+            .iter()
+            .filter(|(_, room)| !room.persistence.is_healthy())
+            .map(|(id, _)| id.clone())
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    /// Aquire a lease on the [`LiveRoom`] for the `room_id`, attempting to cretate it
     /// if it doesn't already exist.
-    pub async fn connect(&self, room_id: &str) -> Result<Arc<LiveRoom>, Error> {
-        // Check if it exists live
-        let r = if let Some(r) = self.get_live(room_id).await {
-            r
-        } else {
+    pub async fn connect(&self, room_id: &str) -> Result<RoomLease, Error> {
+        // Check if it exists live. The connection is counted while holding the lock, so an idle
+        // room can't be evicted in between.
+        let existing = {
+            let live = self.live.read().await;
+            if self.is_shutting_down() {
+                return Err(Error::ShuttingDown);
+            }
+            live.get(room_id).map(|r| {
+                r.inc();
+                r.clone()
+            })
+        };
+
+        let room = match existing {
+            Some(r) => r,
             // Attempt to create group
-            self.create_room_live(room_id).await?
+            None => self.create_room_live(room_id).await?,
         };
 
-        r.inc();
-        Ok(r)
+        Ok(RoomLease {
+            rooms: self.clone(),
+            room_id: room_id.to_string(),
+            room: Some(room),
+        })
     }
 
-    /// Release one connection. If it's the last we evict the room from memory
-    pub async fn disconnect(&self, room_id: &str) {
-        let Some(room) = self.live.read().await.get(room_id).cloned() else {
-            return;
+    /// Close every connection, then wait until every room has written out its updates and taken
+    /// a final snapshot. New connections are refused from now on. Gives up after `deadline`.
+    pub async fn shutdown(&self, deadline: Duration) {
+        self.shutdown.send_replace(true);
+
+        // Evicting a room with connections left only takes it out of `live`. Its persistence
+        // finishes once those connections have closed and released their lease.
+        let rooms: Vec<(String, Arc<PersistenceStatus>)> = {
+            let mut live = self.live.write().await;
+            let mut evicting = self.evicting.write().await;
+            let ids: Vec<String> = live.keys().cloned().collect();
+            for id in ids {
+                evict(&mut live, &mut evicting, &id);
+            }
+            evicting
+                .iter()
+                .map(|(id, status)| (id.clone(), status.clone()))
+                .collect()
         };
+        tracing::info!(rooms = rooms.len(), "closing rooms");
 
-        if room.dec() == 0 {
-            let mut guard = self.live.write().await;
+        let stopped = join_all(rooms.iter().map(|(_, status)| status.stopped()));
+        if tokio::time::timeout(deadline, stopped).await.is_err() {
+            let pending: Vec<&str> = rooms
+                .iter()
+                .filter(|(_, status)| !status.is_stopped())
+                .map(|(id, _)| id.as_str())
+                .collect();
+            tracing::error!(
+                rooms = ?pending,
+                "shutdown deadline passed before every room was written out"
+            );
+        } else {
+            tracing::info!("every room written out");
+        }
+    }
+
+    fn is_shutting_down(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    /// Called once the last connection to a room is released. The room is kept idle for the
+    /// configured timeout, evicting rooms that have been idle for longer if there are too many.
+    async fn idle(&self, room_id: &str) {
+        let evicted = {
+            let mut live = self.live.write().await;
 
             // Re-check so no one else has changed it.
-            if let Some(current) = guard.get(room_id)
-                && current
-                    .conn_count
-                    .load(std::sync::atomic::Ordering::Relaxed)
-                    == 0
-            {
-                println!("Evicting room {room_id}");
-                guard.remove(room_id);
+            let Some(room) = live
+                .get(room_id)
+                .filter(|r| r.conn_count.load(std::sync::atomic::Ordering::Relaxed) == 0)
+            else {
+                return;
+            };
+
+            let since = Instant::now();
+            *room.idle_since.lock().unwrap() = Some(since);
+
+            let mut expired = self.least_recently_idle(&live);
+            if self.eviction.idle_timeout_secs == 0 {
+                expired.push(room_id.to_string());
+            } else {
+                let rooms = self.clone();
+                let room_id = room_id.to_string();
+                let timeout = Duration::from_secs(self.eviction.idle_timeout_secs);
+                tokio::spawn(async move {
+                    tokio::time::sleep(timeout).await;
+                    rooms.evict_if_idle_since(&room_id, since).await;
+                });
+            }
+
+            let mut evicting = self.evicting.write().await;
+            expired
+                .into_iter()
+                .filter_map(|id| {
+                    let status = evict(&mut live, &mut evicting, &id)?;
+                    Some((id, status))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for (room_id, status) in evicted {
+            self.await_eviction(&room_id, status).await;
+        }
+    }
+
+    /// Ids of the idle rooms beyond the limit, the longest idle first.
+    fn least_recently_idle(&self, live: &HashMap<String, Arc<LiveRoom>>) -> Vec<String> {
+        let mut idle: Vec<(Instant, &String)> = live
+            .iter()
+            .filter_map(|(id, room)| Some((room.idle_since()?, id)))
+            .collect();
+        let excess = idle.len().saturating_sub(self.eviction.max_idle_rooms);
+
+        idle.sort();
+        idle.into_iter()
+            .take(excess)
+            .map(|(_, id)| id.clone())
+            .collect()
+    }
+
+    /// Evict the room once its idle timeout has passed, unless it has been used since.
+    async fn evict_if_idle_since(&self, room_id: &str, since: Instant) {
+        let status = {
+            let mut live = self.live.write().await;
+            if live.get(room_id).and_then(|r| r.idle_since()) != Some(since) {
+                return;
             }
+            let mut evicting = self.evicting.write().await;
+            evict(&mut live, &mut evicting, room_id)
+        };
+
+        if let Some(status) = status {
+            self.await_eviction(room_id, status).await;
+        }
+    }
+
+    /// Wait until an evicted room has written out its updates.
+    async fn await_eviction(&self, room_id: &str, status: Arc<PersistenceStatus>) {
+        status.stopped().await;
+
+        let mut evicting = self.evicting.write().await;
+        if evicting
+            .get(room_id)
+            .is_some_and(|current| Arc::ptr_eq(current, &status))
+        {
+            evicting.remove(room_id);
         }
     }
 
-    /// Create a new room in the storage so a [`LiveRoom`] can be created later.
-    pub async fn create_room(&self, room_id: &str) -> Result<(), Error> {
-        let exists = self.storage.room_exists(room_id).await?;
-        if exists {
-            return Err(Error::AlreadyExists);
+    /// Rebuild the document of `room_id` as it was at `at`, see [`history::doc_at`].
+    pub async fn doc_at(&self, room_id: &str, at: At) -> Result<Doc, Error> {
+        history::doc_at(&*self.storage, room_id, at).await
+    }
+
+    /// Restore the text of `room_id` to how it was at `at`, e.g. the `covered_through` of a
+    /// snapshot.
+    ///
+    /// The restore is a regular edit of the live document by `author`, so connected clients
+    /// receive it through the room's broadcast and it is persisted like any other update.
+    pub async fn restore(&self, room_id: &str, at: At, author: &str) -> Result<(), Error> {
+        let target = history::text(&self.doc_at(room_id, at).await?);
+
+        let room = self.connect(room_id).await?;
+        {
+            let awareness = room.awareness.write().await;
+            history::replace_text(awareness.doc(), &target, author);
         }
+        room.release().await;
+
+        Ok(())
+    }
+
+    /// Who inserted each line of the room's text and when, as it is in storage.
+    pub async fn blame(&self, room_id: &str) -> Result<Vec<BlameLine>, Error> {
+        // The log is read after the document, so it covers everything in it.
+        let doc = self.load_doc(room_id).await?;
+        let clients = self.storage.list_clients(room_id).await?;
+        let log = self
+            .storage
+            .load_updates(room_id, LoadUpdatesOptions::default())
+            .await?;
+
+        blame::blame(&doc, &clients, &log)
+    }
+
+    /// Compare the room's script at `from` with the one at `to`, by Fountain element.
+    pub async fn diff(&self, room_id: &str, from: At, to: At) -> Result<ScriptDiff, Error> {
+        let old = history::text(&self.doc_at(room_id, from).await?);
+        let new = history::text(&self.doc_at(room_id, to).await?);
+        Ok(diff::diff(&old, &new, &from.to_string(), &to.to_string()))
+    }
+
+    /// Create the room `dst` holding the document of `src` at `at`.
+    ///
+    /// The fork starts with the whole document as its first update and a snapshot of it, and
+    /// knows the users behind the clients of `src` so blame keeps working. It takes the title
+    /// and description of `src`, and is owned by `owner`.
+    pub async fn fork_room(
+        &self,
+        src: &str,
+        dst: &str,
+        at: At,
+        owner: &str,
+    ) -> Result<RoomInfo, Error> {
+        if dst.is_empty() {
+            return Err(Error::InvalidArgument(
+                "room id must not be empty".to_string(),
+            ));
+        }
+        let seq = match at {
+            At::Seq(seq) => seq,
+            At::Time(time) => history::seq_at(&*self.storage, src, time).await?,
+        };
+        let doc = self.doc_at(src, At::Seq(seq)).await?;
+        let clients = self.storage.list_clients(src).await?;
+        let parent = self
+            .storage
+            .get_room_info(src)
+            .await?
+            .ok_or(Error::NotFound)?;
+
         self.storage
             .create_room(
+                dst,
+                storage::CreateRoomOptions {
+                    fail_if_exists: true,
+                    fork: Some(ForkPoint {
+                        parent: src.to_string(),
+                        seq,
+                    }),
+                    title: parent.title,
+                    owner: Some(owner.to_string()),
+                    description: parent.description,
+                },
+            )
+            .await?;
+
+        if let Err(e) = self.seed_fork(dst, &doc, &clients).await {
+            if let Err(e) = self.storage.delete_room(dst).await {
+                tracing::warn!(room_id = dst, error = %e, "failed to remove half-made fork");
+            }
+            return Err(e);
+        }
+
+        self.storage
+            .get_room_info(dst)
+            .await?
+            .ok_or(Error::NotFound)
+    }
+
+    async fn seed_fork(
+        &self,
+        dst: &str,
+        doc: &Doc,
+        clients: &[storage::ClientInfo],
+    ) -> Result<(), Error> {
+        for c in clients {
+            self.storage
+                .record_client(dst, c.client_id, &c.user_id)
+                .await?;
+        }
+
+        let bytes = doc
+            .transact()
+            .encode_state_as_update_v1(&yrs::StateVector::default());
+        let seq = self.storage.append_update(dst, &bytes, None).await?;
+        self.storage
+            .store_snapshot(
+                dst,
+                storage::Snapshot {
+                    covered_through: seq,
+                    bytes,
+                },
+            )
+            .await
+    }
+
+    /// Merge what `source` changed since the history it shares with `target` into the live
+    /// `target`, on behalf of `author`. A `dry_run` only previews the result.
+    pub async fn merge(
+        &self,
+        target: &str,
+        source: &str,
+        author: &str,
+        dry_run: bool,
+    ) -> Result<Merge, Error> {
+        if target == source {
+            return Err(Error::InvalidArgument(
+                "a room cannot be merged into itself".to_string(),
+            ));
+        }
+        let source_doc = self.load_doc(source).await?;
+
+        let room = self.connect(target).await?;
+        let res = self
+            .merge_into(&room, &source_doc, source, author, dry_run)
+            .await;
+        room.release().await;
+        res
+    }
+
+    async fn merge_into(
+        &self,
+        room: &RoomLease,
+        source_doc: &Doc,
+        source: &str,
+        author: &str,
+        dry_run: bool,
+    ) -> Result<Merge, Error> {
+        if !dry_run {
+            // So blame can tell who wrote the merged text.
+            for c in self.storage.list_clients(source).await? {
+                room.record_client(c.client_id, &c.user_id).await?;
+            }
+        }
+
+        let awareness = room.awareness.write().await;
+        let doc = awareness.doc();
+        let changes = merge::missing_changes(doc, source_doc)?;
+        let text = merge::preview(doc, &changes)?;
+        let changed = text != history::text(doc);
+        if !dry_run {
+            merge::apply(doc, &changes, author)?;
+        }
+
+        Ok(Merge { text, changed })
+    }
+
+    /// Name the room's document at `seq`, or at the end of its log if `None`.
+    ///
+    /// A snapshot at exactly `seq` is stored along with it, so the version outlives retention.
+    pub async fn create_version(
+        &self,
+        room_id: &str,
+        label: &str,
+        seq: Option<LogSeq>,
+        author: &str,
+        note: Option<String>,
+    ) -> Result<Version, Error> {
+        if label.trim().is_empty() {
+            return Err(Error::InvalidArgument(
+                "label must not be empty".to_string(),
+            ));
+        }
+        let seq = match seq {
+            Some(seq) => seq,
+            None => {
+                self.storage
+                    .get_room_info(room_id)
+                    .await?
+                    .ok_or(Error::NotFound)?
+                    .last_seq
+            }
+        };
+        // Fails before anything is stored if the seq is past the log or already pruned.
+        let doc = self.doc_at(room_id, At::Seq(seq)).await?;
+
+        let version = self
+            .storage
+            .create_version(
                 room_id,
+                NewVersion {
+                    label: label.to_string(),
+                    seq,
+                    author: Some(author.to_string()),
+                    note,
+                },
+            )
+            .await?;
+
+        // Pinned after the version exists, so retention cannot remove the snapshot in between.
+        if let Err(e) = self.pin_snapshot(room_id, seq, &doc).await {
+            if let Err(e) = self.storage.delete_version(room_id, label).await {
+                tracing::warn!(room_id, label, error = %e, "failed to remove unpinned version");
+            }
+            return Err(e);
+        }
+
+        Ok(version)
+    }
+
+    /// The named versions of the room, ordered by seq.
+    pub async fn list_versions(&self, room_id: &str) -> Result<Vec<Version>, Error> {
+        self.storage.list_versions(room_id).await
+    }
+
+    /// Remove a named version. Its snapshot is left to retention.
+    pub async fn delete_version(&self, room_id: &str, label: &str) -> Result<(), Error> {
+        self.storage.delete_version(room_id, label).await
+    }
+
+    pub async fn version(&self, room_id: &str, label: &str) -> Result<Version, Error> {
+        self.storage
+            .get_version(room_id, label)
+            .await?
+            .ok_or(Error::NotFound)
+    }
+
+    /// A named version and its document.
+    pub async fn version_doc(&self, room_id: &str, label: &str) -> Result<(Version, Doc), Error> {
+        let version = self.version(room_id, label).await?;
+        let doc = self.doc_at(room_id, At::Seq(version.seq)).await?;
+        Ok((version, doc))
+    }
+
+    /// Store a snapshot of `doc` at exactly `seq` unless there already is one.
+    async fn pin_snapshot(&self, room_id: &str, seq: LogSeq, doc: &Doc) -> Result<(), Error> {
+        // The empty document needs no snapshot.
+        if seq == 0 || self.storage.load_snapshot_at(room_id, seq).await?.is_some() {
+            return Ok(());
+        }
+
+        let bytes = doc
+            .transact()
+            .encode_state_as_update_v1(&yrs::StateVector::default());
+        self.storage
+            .store_snapshot(
+                room_id,
+                storage::Snapshot {
+                    covered_through: seq,
+                    bytes,
+                },
+            )
+            .await
+    }
+
+    /// Every room `user_id` may view, ordered by id.
+    pub async fn list_rooms(&self, user_id: &str) -> Result<Vec<RoomInfo>, Error> {
+        let memberships: HashMap<String, Role> = self
+            .storage
+            .list_memberships(user_id)
+            .await?
+            .into_iter()
+            .collect();
+        let mut rooms = self.storage.list_rooms().await?;
+        rooms
+            .retain(|r| effective_role(r, user_id, memberships.get(&r.room_id).copied()).is_some());
+        Ok(rooms)
+    }
+
+    pub async fn room_info(&self, room_id: &str) -> Result<RoomInfo, Error> {
+        self.storage
+            .get_room_info(room_id)
+            .await?
+            .ok_or(Error::NotFound)
+    }
+
+    /// Create a new room owned by `owner` under a generated id, so a [`LiveRoom`] can be created
+    /// later.
+    pub async fn create_room(
+        &self,
+        owner: &str,
+        title: Option<String>,
+        description: Option<String>,
+    ) -> Result<RoomInfo, Error> {
+        let room_id = uuid::Uuid::new_v4().to_string();
+        self.storage
+            .create_room(
+                &room_id,
                 storage::CreateRoomOptions {
+                    fail_if_exists: true,
+                    title,
+                    owner: Some(owner.to_string()),
+                    description,
                     ..Default::default()
                 },
             )
             .await?;
+        self.room_info(&room_id).await
+    }
+
+    pub async fn update_room(
+        &self,
+        room_id: &str,
+        update: RoomMetadataUpdate,
+    ) -> Result<RoomInfo, Error> {
+        let owner_changed = update.owner.is_some();
+        self.storage.update_room_metadata(room_id, update).await?;
+        if owner_changed {
+            self.access_changed(room_id).await;
+        }
+        self.room_info(room_id).await
+    }
+
+    /// The role `user_id` has in the room, `None` if they may not even view it.
+    pub async fn role(&self, room_id: &str, user_id: &str) -> Result<Option<Role>, Error> {
+        let info = self.room_info(room_id).await?;
+        self.role_in(&info, user_id).await
+    }
+
+    async fn role_in(&self, info: &RoomInfo, user_id: &str) -> Result<Option<Role>, Error> {
+        let member = self.storage.get_member(&info.room_id, user_id).await?;
+        Ok(effective_role(info, user_id, member.map(|m| m.role)))
+    }
+
+    /// Check that `user_id` has at least the role `needed` in the room and return their role.
+    ///
+    /// A room without an owner, from before access control or the seeded demo room, becomes
+    /// the room of the first logged in user to use it. Someone who may not view the room is told
+    /// it does not exist, so room ids can't be probed.
+    pub async fn authorize(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        needed: Role,
+    ) -> Result<Role, Error> {
+        let mut info = self.room_info(room_id).await?;
+        if info.owner.is_none()
+            && !crate::auth::is_guest(user_id)
+            && self.storage.claim_room(room_id, user_id).await?
+        {
+            tracing::info!(room_id, user_id, "claimed room without owner");
+            info.owner = Some(user_id.to_string());
+        }
+
+        match self.role_in(&info, user_id).await? {
+            Some(role) if role >= needed => Ok(role),
+            Some(_) => Err(Error::Forbidden),
+            None => Err(Error::NotFound),
+        }
+    }
+
+    /// The users granted a role in the room. Its owner is not among them.
+    pub async fn list_members(&self, room_id: &str) -> Result<Vec<Member>, Error> {
+        self.storage.list_members(room_id).await
+    }
+
+    /// Grant `role` to `user_id` in the room, replacing the role they had.
+    pub async fn set_member(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        role: Role,
+    ) -> Result<Member, Error> {
+        let info = self.room_info(room_id).await?;
+        if info.owner.as_deref() == Some(user_id) {
+            return Err(Error::InvalidArgument(
+                "the owner of a room keeps their role".to_string(),
+            ));
+        }
+        if crate::auth::is_guest(user_id) {
+            return Err(Error::InvalidArgument(
+                "guests only get roles through share links".to_string(),
+            ));
+        }
+        let member = self
+            .storage
+            .set_member(room_id, user_id, role, None)
+            .await?;
+        self.access_changed(room_id).await;
+        Ok(member)
+    }
+
+    pub async fn remove_member(&self, room_id: &str, user_id: &str) -> Result<(), Error> {
+        self.storage.remove_member(room_id, user_id).await?;
+        self.access_changed(room_id).await;
         Ok(())
     }
 
-    /// Gets the [`LiveRoom`] for the room if it exists in memory
-    async fn get_live(&self, room_id: &str) -> Option<Arc<LiveRoom>> {
-        self.live.read().await.get(room_id).cloned()
+    /// Create a share granting `role` in the room to whoever uses it, until `expires_at` or
+    /// until it was used `max_uses` times.
+    pub async fn create_share(
+        &self,
+        room_id: &str,
+        role: Role,
+        created_by: &str,
+        expires_at: Option<DateTime<Utc>>,
+        max_uses: Option<u32>,
+    ) -> Result<Share, Error> {
+        if role == Role::Owner {
+            return Err(Error::InvalidArgument(
+                "a share can't make anyone an owner".to_string(),
+            ));
+        }
+        if max_uses == Some(0) {
+            return Err(Error::InvalidArgument(
+                "a share must be usable at least once".to_string(),
+            ));
+        }
+        if expires_at.is_some_and(|at| at <= Utc::now()) {
+            return Err(Error::InvalidArgument(
+                "a share must expire in the future".to_string(),
+            ));
+        }
+
+        self.storage
+            .create_share(
+                room_id,
+                NewShare {
+                    share_id: uuid::Uuid::new_v4().to_string(),
+                    token: rand::rng()
+                        .sample_iter(&Alphanumeric)
+                        .take(SHARE_TOKEN_LEN)
+                        .map(char::from)
+                        .collect(),
+                    role,
+                    created_by: Some(created_by.to_string()),
+                    expires_at,
+                    max_uses,
+                },
+            )
+            .await
     }
 
-    /// Creates a [`LiveRoom`] for the room, if it already exists return the existing
-    /// [`LiveRoom`]
-    async fn create_room_live(&self, room_id: &str) -> Result<Arc<LiveRoom>, Error> {
-        let mut guard = self.live.write().await;
-        if let Some(r) = guard.get(room_id).cloned() {
-            return Ok(r);
+    pub async fn list_shares(&self, room_id: &str) -> Result<Vec<Share>, Error> {
+        self.storage.list_shares(room_id).await
+    }
+
+    /// Revoke a share, taking away the roles granted through it.
+    pub async fn delete_share(&self, room_id: &str, share_id: &str) -> Result<(), Error> {
+        self.storage.delete_share(room_id, share_id).await?;
+        self.access_changed(room_id).await;
+        Ok(())
+    }
+
+    /// Have the connections to the room, if it is live, check their role again.
+    async fn access_changed(&self, room_id: &str) {
+        if let Some(room) = self.live.read().await.get(room_id) {
+            room.access.send_replace(());
+        }
+    }
+
+    /// Use the share with `token` on behalf of `user_id`, granting them its role unless they
+    /// already have one at least as high. Every use counts towards its `max_uses`.
+    ///
+    /// A role granted directly is never replaced, so revoking the share can't take it away.
+    ///
+    /// An unknown, expired or used up token is [`Error::NotFound`].
+    pub async fn redeem_share(&self, token: &str, user_id: &str) -> Result<Share, Error> {
+        let share = self.storage.use_share(token, Utc::now()).await?;
+        let info = self.room_info(&share.room_id).await?;
+        let member = self.storage.get_member(&share.room_id, user_id).await?;
+        let direct = member.as_ref().is_some_and(|m| m.share_id.is_none());
+        let role = effective_role(&info, user_id, member.map(|m| m.role));
+        if !direct && role.is_none_or(|role| role < share.role) {
+            self.storage
+                .set_member(&share.room_id, user_id, share.role, Some(&share.share_id))
+                .await?;
+            self.access_changed(&share.room_id).await;
         }
+        Ok(share)
+    }
+
+    /// Delete the room and everything stored for it.
+    ///
+    /// A live room is evicted and its connections are closed. Whatever they still send is
+    /// dropped rather than written to a room that no longer exists.
+    pub async fn delete_room(&self, room_id: &str) -> Result<(), Error> {
+        if !self.storage.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
+        let evicted = {
+            let mut live = self.live.write().await;
+            let mut evicting = self.evicting.write().await;
+            if let Some(room) = live.get(room_id) {
+                room.deleted.send_replace(true);
+            }
+            let evicted = evict(&mut live, &mut evicting, room_id);
+            // Also covers a room evicted earlier that is still writing out its updates.
+            if let Some(status) = evicting.get(room_id) {
+                status.discard();
+            }
+            evicted
+        };
+        if let Some(status) = evicted {
+            let rooms = self.clone();
+            let room_id = room_id.to_string();
+            tokio::spawn(async move { rooms.await_eviction(&room_id, status).await });
+        }
+
+        self.storage.delete_room(room_id).await
+    }
+
+    /// Creates a [`LiveRoom`] for the room and counts a connection to it, if it already exists
+    /// return the existing [`LiveRoom`]
+    async fn create_room_live(&self, room_id: &str) -> Result<Arc<LiveRoom>, Error> {
+        let mut guard = loop {
+            let guard = self.live.write().await;
+            if self.is_shutting_down() {
+                return Err(Error::ShuttingDown);
+            }
+            if let Some(r) = guard.get(room_id).cloned() {
+                r.inc();
+                return Ok(r);
+            }
 
-        let (awareness, sub) = self.make_awareness_and_persitence(room_id).await?;
+            // A room that was just evicted may still be writing out its last updates. Loading it
+            // before that would miss them.
+            let mut evicting = self.evicting.write().await;
+            match evicting.get(room_id).cloned() {
+                Some(status) if !status.is_stopped() => {
+                    drop(evicting);
+                    drop(guard);
+                    status.stopped().await;
+                }
+                Some(_) => {
+                    evicting.remove(room_id);
+                    break guard;
+                }
+                None => break guard,
+            }
+        };
+
+        let (awareness, sub, persistence) = self.make_awareness_and_persitence(room_id).await?;
         let bcast = Arc::new(BroadcastGroup::new(awareness.clone(), self.bcast_capacity).await);
 
         let room = Arc::new(LiveRoom {
             bcast,
             awareness,
+            persistence,
             // Needs to be stored. Unsubsribes when dropped.
             _sub: sub,
-            conn_count: AtomicUsize::new(0),
+            conn_count: AtomicUsize::new(1),
+            idle_since: std::sync::Mutex::new(None),
+            deleted: watch::Sender::new(false),
+            access: watch::Sender::new(()),
         });
 
         guard.insert(room_id.to_string(), room.clone());
@@ -140,61 +958,40 @@ impl RoomManager {
     async fn make_awareness_and_persitence(
         &self,
         room_id: &str,
-    ) -> Result<(AwarenessRef, Subscription), Error> {
+    ) -> Result<(AwarenessRef, Subscription, Arc<PersistenceStatus>), Error> {
         let doc = self.load_doc(room_id).await?;
+        let stored = Doc::new();
+        stored.transact_mut().apply_update(yrs::Update::decode_v1(
+            &doc.transact()
+                .encode_state_as_update_v1(&yrs::StateVector::default()),
+        )?);
 
-        let (tx, mut rx) = mpsc::channel::<Vec<u8>>(self.persist_queue_capacity);
-        let storage = self.storage.clone();
-        let room_id_owned = room_id.to_string();
-
-        let doc_for_snapshots = doc.clone();
-        let snapshot_every = self.snapshot_every_n_updates;
-
-        tokio::spawn(async move {
-            let mut since_snapshot = 0;
-            let mut last_seq;
-
-            while let Some(update_bytes) = rx.recv().await {
-                match storage.append_update(&room_id_owned, &update_bytes).await {
-                    Ok(seq) => {
-                        last_seq = seq;
-                        since_snapshot += 1;
-
-                        if since_snapshot >= snapshot_every {
-                            // Encode full doc state as an update (v1) and store snapshot.
-                            let bytes = doc_for_snapshots
-                                .transact()
-                                .encode_state_as_update_v1(&yrs::StateVector::default());
-
-                            let snap = storage::Snapshot {
-                                covered_through: last_seq,
-                                bytes,
-                            };
-
-                            // Attempt to store snapshot. On error just log and continue.
-                            if let Err(e) = storage.store_snapshot(&room_id_owned, snap).await {
-                                eprintln!("snapshot failed room={room_id_owned}: {e:?}");
-                            } else {
-                                since_snapshot = 0;
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        // Attempt to store change. On error just log and continue in-memory doc.
-                        eprintln!("append_update failed room={room_id_owned}: {e:?}");
-                    }
-                }
+        // Updates left in the journal by an earlier run are part of the document, even though
+        // they have not reached storage yet.
+        let config = &self.persistence.journal;
+        let journal = Journal::open(&config.dir, room_id, config.max_bytes).await?;
+        let journaled = journal.read_all().await?;
+        {
+            let mut txn = doc.transact_mut();
+            for entry in journaled {
+                txn.apply_update(yrs::Update::decode_v1(&entry.bytes)?);
             }
-        });
+        }
 
-        let sub = doc
-            .observe_update_v1(move |_txn, e| {
-                // Attempt to send if full we drop.
-                let _ = tx.try_send(e.update.clone());
-            })
-            .expect("Subscription function should work.");
+        let awareness: AwarenessRef = Arc::new(RwLock::new(Awareness::new(doc.clone())));
 
-        Ok((Arc::new(RwLock::new(Awareness::new(doc))), sub))
+        let (sub, status) = persistence::spawn(
+            self.storage.clone(),
+            room_id,
+            &doc,
+            stored,
+            awareness.clone(),
+            journal,
+            self.persistence.clone(),
+            self.metrics.clone(),
+        );
+
+        Ok((awareness, sub, status))
     }
 
     async fn load_doc(&self, room_id: &str) -> Result<Doc, Error> {
@@ -234,3 +1031,964 @@ impl RoomManager {
         Ok(doc)
     }
 }
+
+/// Length of the secret in share links, about 256 bits worth of alphanumerics.
+const SHARE_TOKEN_LEN: usize = 43;
+
+/// The role of `user_id` in the room described by `info`, given the role they were granted.
+fn effective_role(info: &RoomInfo, user_id: &str, granted: Option<Role>) -> Option<Role> {
+    match info.owner.as_deref() {
+        Some(owner) if owner == user_id => Some(Role::Owner),
+        _ => granted,
+    }
+}
+
+/// Move the room from `live` to `evicting`. Its persistence finishes once the last handle to the
+/// room, and with it the subscription, is gone.
+fn evict(
+    live: &mut HashMap<String, Arc<LiveRoom>>,
+    evicting: &mut HashMap<String, Arc<PersistenceStatus>>,
+    room_id: &str,
+) -> Option<Arc<PersistenceStatus>> {
+    let room = live.remove(room_id)?;
+    tracing::info!(room_id, "evicting room");
+
+    let status = room.persistence.clone();
+    evicting.insert(room_id.to_string(), status.clone());
+    Some(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::time::Duration;
+
+    use yrs::{GetString, ReadTxn, Text, Transact};
+
+    use super::LiveRoom;
+    use crate::config::{self, Batching, Eviction, Retry, SnapshotRetention, UpdateRetention};
+    use crate::rooms::persistence::PersistenceSettings;
+    use crate::rooms::storage::{
+        CreateRoomOptions, LoadUpdatesOptions, PruneUpdatesOptions, Role, Storage,
+    };
+    use crate::rooms::{CloseReason, Error, InMemoryStorage, RoomManager, retention};
+
+    const ROOM: &str = "room";
+
+    /// Evict rooms as soon as their last connection closes.
+    const EVICT_RIGHT_AWAY: Eviction = Eviction {
+        idle_timeout_secs: 0,
+        max_idle_rooms: 0,
+    };
+
+    /// A manager over a fresh in-memory storage holding an empty [`ROOM`].
+    async fn manager(test: &str, eviction: Eviction) -> (RoomManager, Arc<InMemoryStorage>) {
+        manager_with(settings(test), eviction).await
+    }
+
+    /// Like [`manager`], persisting with `settings`.
+    async fn manager_with(
+        settings: PersistenceSettings,
+        eviction: Eviction,
+    ) -> (RoomManager, Arc<InMemoryStorage>) {
+        let storage = Arc::new(InMemoryStorage::new().await);
+        storage
+            .create_room(ROOM, CreateRoomOptions::default())
+            .await
+            .unwrap();
+
+        (
+            RoomManager::new(storage.clone(), 32, settings, eviction),
+            storage,
+        )
+    }
+
+    fn settings(test: &str) -> PersistenceSettings {
+        PersistenceSettings {
+            queue_capacity: 1024,
+            snapshot_every_n_updates: 100,
+            batching: Batching {
+                window_ms: 50,
+                max_updates: 256,
+                merge: true,
+            },
+            update_retention: UpdateRetention {
+                keep_last: 1000,
+                keep_days: 7,
+            },
+            snapshot_retention: SnapshotRetention {
+                keep_all_hours: 24,
+                keep_hourly_days: 7,
+            },
+            retry: Retry {
+                initial_backoff_ms: 10,
+                max_backoff_ms: 100,
+            },
+            journal: config::Journal {
+                dir: std::env::temp_dir()
+                    .join(format!("dionysus-lifecycle-{}-{test}", std::process::id())),
+                max_bytes: 1 << 20,
+            },
+        }
+    }
+
+    async fn type_text(room: &LiveRoom, chunk: &str) {
+        let awareness = room.awareness.write().await;
+        let doc = awareness.doc();
+        let text = doc.get_or_insert_text("codemirror");
+        let mut txn = doc.transact_mut();
+        let len = text.len(&txn);
+        text.insert(&mut txn, len, chunk);
+    }
+
+    async fn text(room: &LiveRoom) -> String {
+        let awareness = room.awareness.read().await;
+        let doc = awareness.doc();
+        let text = doc.get_or_insert_text("codemirror");
+        text.get_string(&doc.transact())
+    }
+
+    #[tokio::test]
+    async fn reconnect_right_after_last_disconnect_keeps_updates() {
+        let (rooms, _) = manager("reconnect", EVICT_RIGHT_AWAY).await;
+
+        let room = rooms.connect(ROOM).await.unwrap();
+        for word in ["INT. ", "HOUSE ", "- DAY"] {
+            type_text(&room, word).await;
+        }
+
+        // The updates are still waiting for the batch window when the room is evicted, and the
+        // reconnect happens while they are being written.
+        let ((), room) = tokio::join!(room.release(), async {
+            tokio::task::yield_now().await;
+            rooms.connect(ROOM).await.unwrap()
+        });
+        assert_eq!(text(&room).await, "INT. HOUSE - DAY");
+
+        type_text(&room, "\n").await;
+        room.release().await;
+
+        let room = rooms.connect(ROOM).await.unwrap();
+        assert_eq!(text(&room).await, "INT. HOUSE - DAY\n");
+    }
+
+    #[tokio::test]
+    async fn eviction_takes_final_snapshot() {
+        let (rooms, storage) = manager("snapshot", EVICT_RIGHT_AWAY).await;
+
+        let room = rooms.connect(ROOM).await.unwrap();
+        type_text(&room, "FADE IN:").await;
+        room.release().await;
+
+        let info = storage.get_room_info(ROOM).await.unwrap().unwrap();
+        let snapshot = info.latest_snapshot.expect("final snapshot");
+        assert_eq!(snapshot.covered_through, info.last_seq);
+    }
+
+    #[tokio::test]
+    async fn snapshots_hold_only_what_they_cover() {
+        let mut settings = settings("snapshot-cover");
+        settings.snapshot_every_n_updates = 1;
+        settings.batching = Batching {
+            window_ms: 50,
+            max_updates: 2,
+            merge: false,
+        };
+        let (rooms, storage) = manager_with(settings, EVICT_RIGHT_AWAY).await;
+
+        // "C" is still queued when the batch of "A" and "B" is appended and snapshotted.
+        let room = rooms.connect(ROOM).await.unwrap();
+        for chunk in ["A", "B", "C"] {
+            type_text(&room, chunk).await;
+        }
+        room.release().await;
+
+        let snapshot = storage.load_snapshot_best(ROOM, Some(2)).await.unwrap();
+        assert_eq!(snapshot.expect("snapshot").covered_through, 2);
+
+        let doc = rooms
+            .doc_at(ROOM, crate::rooms::history::At::Seq(2))
+            .await
+            .unwrap();
+        let text = doc.get_or_insert_text("codemirror");
+        assert_eq!(text.get_string(&doc.transact()), "AB");
+    }
+
+    /// Wait for the room to be evicted and dropped.
+    async fn wait_evicted(room: std::sync::Weak<LiveRoom>) {
+        while room.strong_count() > 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_room_stays_loaded_until_timeout() {
+        let eviction = Eviction {
+            idle_timeout_secs: 30,
+            max_idle_rooms: 64,
+        };
+        let (rooms, _) = manager("idle", eviction).await;
+
+        let room = rooms.connect(ROOM).await.unwrap();
+        type_text(&room, "EXT. STREET - NIGHT").await;
+        let weak = room.downgrade();
+        room.release().await;
+
+        // Reconnecting within the timeout gets the same room, and resets the timeout.
+        tokio::time::sleep(Duration::from_secs(20)).await;
+        let room = rooms.connect(ROOM).await.unwrap();
+        assert!(weak.ptr_eq(&room.downgrade()));
+        room.release().await;
+
+        tokio::time::sleep(Duration::from_secs(20)).await;
+        assert!(weak.upgrade().is_some());
+
+        tokio::time::sleep(Duration::from_secs(15)).await;
+        wait_evicted(weak).await;
+
+        let room = rooms.connect(ROOM).await.unwrap();
+        assert_eq!(text(&room).await, "EXT. STREET - NIGHT");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn longest_idle_room_is_evicted_beyond_limit() {
+        let eviction = Eviction {
+            idle_timeout_secs: 3600,
+            max_idle_rooms: 1,
+        };
+        let (rooms, storage) = manager("lru", eviction).await;
+        storage
+            .create_room("other", CreateRoomOptions::default())
+            .await
+            .unwrap();
+
+        let first = rooms.connect(ROOM).await.unwrap();
+        let first_weak = first.downgrade();
+        first.release().await;
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let second = rooms.connect("other").await.unwrap();
+        let second_weak = second.downgrade();
+        second.release().await;
+
+        wait_evicted(first_weak).await;
+        assert!(second_weak.upgrade().is_some());
+    }
+
+    #[tokio::test]
+    async fn dropped_lease_releases_connection() {
+        let (rooms, storage) = manager("dropped", EVICT_RIGHT_AWAY).await;
+
+        let room = rooms.connect(ROOM).await.unwrap();
+        type_text(&room, "CUT TO:").await;
+        let weak = room.downgrade();
+
+        // As if the upgrade never completed or the connection task was aborted.
+        drop(room);
+        wait_evicted(weak).await;
+
+        let room = rooms.connect(ROOM).await.unwrap();
+        assert_eq!(text(&room).await, "CUT TO:");
+        assert!(storage.get_room_info(ROOM).await.unwrap().unwrap().last_seq > 0);
+    }
+
+    #[tokio::test]
+    async fn shutdown_closes_connections_and_writes_out_rooms() {
+        let eviction = Eviction {
+            idle_timeout_secs: 3600,
+            max_idle_rooms: 64,
+        };
+        let (rooms, storage) = manager("shutdown", eviction).await;
+        rooms.set_member(ROOM, "mara", Role::Editor).await.unwrap();
+
+        let room = rooms.connect(ROOM).await.unwrap();
+        type_text(&room, "THE END").await;
+
+        // Stands in for a websocket peer.
+        let peer = tokio::spawn(async move {
+            assert_eq!(
+                room.close_requested("mara", Role::Editor).await,
+                CloseReason::ShuttingDown
+            );
+            room.release().await;
+        });
+
+        rooms.shutdown(Duration::from_secs(5)).await;
+        peer.await.unwrap();
+
+        let info = storage.get_room_info(ROOM).await.unwrap().unwrap();
+        assert!(info.last_seq > 0);
+        assert_eq!(info.latest_snapshot.unwrap().covered_through, info.last_seq);
+        assert!(matches!(
+            rooms.connect(ROOM).await,
+            Err(crate::rooms::Error::ShuttingDown)
+        ));
+    }
+
+    #[tokio::test]
+    async fn restore_edits_live_room_back_to_earlier_version() {
+        let (rooms, storage) = manager("restore", EVICT_RIGHT_AWAY).await;
+
+        let room = rooms.connect(ROOM).await.unwrap();
+        type_text(&room, "INT. HOUSE - DAY\n").await;
+        room.release().await;
+        let version = storage.get_room_info(ROOM).await.unwrap().unwrap().last_seq;
+
+        let room = rooms.connect(ROOM).await.unwrap();
+        type_text(&room, "\nA door slams.\n").await;
+
+        rooms
+            .restore(ROOM, crate::rooms::history::At::Seq(version), "mara")
+            .await
+            .unwrap();
+        assert_eq!(text(&room).await, "INT. HOUSE - DAY\n");
+
+        // The restore is persisted like any other edit, on behalf of whoever asked for it.
+        room.release().await;
+        let room = rooms.connect(ROOM).await.unwrap();
+        assert_eq!(text(&room).await, "INT. HOUSE - DAY\n");
+
+        let opts = LoadUpdatesOptions {
+            from: Some(version + 1),
+            to: None,
+        };
+        let updates = storage.load_updates(ROOM, opts).await.unwrap();
+        let authors: Vec<Option<&str>> = updates.iter().map(|u| u.author.as_deref()).collect();
+        assert_eq!(authors, [None, Some("mara")]);
+    }
+
+    #[tokio::test]
+    async fn versions_keep_their_snapshot_through_retention() {
+        let (rooms, storage) = manager("versions", EVICT_RIGHT_AWAY).await;
+
+        let doc = yrs::Doc::new();
+        for line in [
+            "INT. HOUSE - DAY\n",
+            "\nA door slams.\n",
+            "\nMARA\nWho's there?\n",
+        ] {
+            let update = {
+                let text = doc.get_or_insert_text("codemirror");
+                let mut txn = doc.transact_mut();
+                let before = txn.state_vector();
+                let len = text.len(&txn);
+                text.insert(&mut txn, len, line);
+                txn.encode_state_as_update_v1(&before)
+            };
+            storage.append_update(ROOM, &update, None).await.unwrap();
+        }
+
+        let version = rooms
+            .create_version(ROOM, "first draft", Some(2), "mara", None)
+            .await
+            .unwrap();
+        assert_eq!(version.author.as_deref(), Some("mara"));
+        assert!(storage.load_snapshot_at(ROOM, 2).await.unwrap().is_some());
+        assert!(matches!(
+            rooms
+                .create_version(ROOM, "first draft", None, "mara", None)
+                .await,
+            Err(Error::AlreadyExists)
+        ));
+
+        // Everything but the newest snapshot is past retention, and the log is pruned up to it.
+        let room = rooms.connect(ROOM).await.unwrap();
+        room.release().await;
+        let policy = SnapshotRetention {
+            keep_all_hours: 0,
+            keep_hourly_days: 0,
+        };
+        retention::gc_snapshots(storage.as_ref(), ROOM, &policy)
+            .await
+            .unwrap();
+        storage
+            .prune_updates_through(ROOM, 3, PruneUpdatesOptions::default())
+            .await
+            .unwrap();
+
+        let (_, doc) = rooms.version_doc(ROOM, "first draft").await.unwrap();
+        assert_eq!(
+            crate::rooms::history::text(&doc),
+            "INT. HOUSE - DAY\n\nA door slams.\n"
+        );
+        assert!(matches!(
+            rooms
+                .create_version(ROOM, "opening", Some(1), "mara", None)
+                .await,
+            Err(Error::HistoryPruned)
+        ));
+    }
+
+    #[tokio::test]
+    async fn fork_starts_from_the_chosen_version() {
+        let (rooms, storage) = manager("fork", EVICT_RIGHT_AWAY).await;
+        storage.record_client(ROOM, 7, "mara").await.unwrap();
+
+        let room = rooms.connect(ROOM).await.unwrap();
+        type_text(&room, "INT. HOUSE - DAY\n").await;
+        room.release().await;
+        let fork_point = storage.get_room_info(ROOM).await.unwrap().unwrap().last_seq;
+
+        let room = rooms.connect(ROOM).await.unwrap();
+        type_text(&room, "\nA door slams.\n").await;
+        room.release().await;
+
+        let info = rooms
+            .fork_room(
+                ROOM,
+                "alternate",
+                crate::rooms::history::At::Seq(fork_point),
+                "mara",
+            )
+            .await
+            .unwrap();
+        let fork = info.fork.unwrap();
+        assert_eq!((fork.parent.as_str(), fork.seq), (ROOM, fork_point));
+        assert!(info.latest_snapshot.is_some());
+        assert_eq!(storage.list_clients("alternate").await.unwrap().len(), 1);
+
+        // The fork goes its own way.
+        let alternate = rooms.connect("alternate").await.unwrap();
+        assert_eq!(text(&alternate).await, "INT. HOUSE - DAY\n");
+        type_text(&alternate, "\nSilence.\n").await;
+        alternate.release().await;
+
+        let room = rooms.connect(ROOM).await.unwrap();
+        assert_eq!(text(&room).await, "INT. HOUSE - DAY\n\nA door slams.\n");
+        room.release().await;
+
+        assert!(matches!(
+            rooms
+                .fork_room(ROOM, "alternate", crate::rooms::history::At::Seq(0), "mara")
+                .await,
+            Err(Error::AlreadyExists)
+        ));
+    }
+
+    #[tokio::test]
+    async fn merge_brings_fork_changes_into_live_parent() {
+        let (rooms, storage) = manager("merge", EVICT_RIGHT_AWAY).await;
+
+        let room = rooms.connect(ROOM).await.unwrap();
+        type_text(&room, "INT. HOUSE - DAY\n").await;
+        room.release().await;
+        let seq = storage.get_room_info(ROOM).await.unwrap().unwrap().last_seq;
+        rooms
+            .fork_room(ROOM, "pass", crate::rooms::history::At::Seq(seq), "mara")
+            .await
+            .unwrap();
+
+        let pass = rooms.connect("pass").await.unwrap();
+        type_text(&pass, "\nA door slams.\n").await;
+        pass.release().await;
+
+        // The parent keeps being edited meanwhile.
+        let room = rooms.connect(ROOM).await.unwrap();
+        {
+            let awareness = room.awareness.write().await;
+            let doc = awareness.doc();
+            let text = doc.get_or_insert_text("codemirror");
+            text.insert(&mut doc.transact_mut(), 0, "FADE IN:\n\n");
+        }
+
+        let merged = "FADE IN:\n\nINT. HOUSE - DAY\n\nA door slams.\n";
+        let preview = rooms.merge(ROOM, "pass", "mara", true).await.unwrap();
+        assert_eq!((preview.text.as_str(), preview.changed), (merged, true));
+        assert_eq!(text(&room).await, "FADE IN:\n\nINT. HOUSE - DAY\n");
+
+        rooms.merge(ROOM, "pass", "mara", false).await.unwrap();
+        assert_eq!(text(&room).await, merged);
+        room.release().await;
+
+        let updates = storage
+            .load_updates(ROOM, LoadUpdatesOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(updates.last().unwrap().author.as_deref(), Some("mara"));
+        assert!(
+            !rooms
+                .merge(ROOM, "pass", "mara", true)
+                .await
+                .unwrap()
+                .changed
+        );
+
+        // A room with a history of its own cannot be merged.
+        assert!(matches!(
+            rooms.merge(ROOM, "demo-room-1", "mara", true).await,
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn create_room_generates_id_and_owner() {
+        let (rooms, _) = manager("create", EVICT_RIGHT_AWAY).await;
+
+        let a = rooms
+            .create_room("mara", Some("The Kettle".to_string()), None)
+            .await
+            .unwrap();
+        let b = rooms.create_room("mara", None, None).await.unwrap();
+        assert_ne!(a.room_id, b.room_id);
+        assert_eq!(a.owner.as_deref(), Some("mara"));
+        assert_eq!(a.last_seq, 0);
+
+        let update = crate::rooms::storage::RoomMetadataUpdate {
+            description: Some("A short about tea.".to_string()),
+            ..Default::default()
+        };
+        let updated = rooms.update_room(&a.room_id, update).await.unwrap();
+        assert_eq!(updated.title.as_deref(), Some("The Kettle"));
+        assert_eq!(updated.description.as_deref(), Some("A short about tea."));
+
+        let listed = rooms.list_rooms("mara").await.unwrap();
+        assert!(listed.contains(&updated));
+    }
+
+    #[tokio::test]
+    async fn roles_decide_what_users_may_do() {
+        let (rooms, _) = manager("roles", EVICT_RIGHT_AWAY).await;
+        let room = rooms.create_room("mara", None, None).await.unwrap();
+        let id = room.room_id.as_str();
+
+        assert_eq!(rooms.role(id, "mara").await.unwrap(), Some(Role::Owner));
+        assert_eq!(rooms.role(id, "daniel").await.unwrap(), None);
+        // Outsiders can't tell the room from a missing one.
+        assert!(matches!(
+            rooms.authorize(id, "daniel", Role::Viewer).await,
+            Err(Error::NotFound)
+        ));
+        assert!(!rooms.list_rooms("daniel").await.unwrap().contains(&room));
+
+        rooms.set_member(id, "daniel", Role::Viewer).await.unwrap();
+        assert_eq!(
+            rooms.authorize(id, "daniel", Role::Viewer).await.unwrap(),
+            Role::Viewer
+        );
+        assert!(matches!(
+            rooms.authorize(id, "daniel", Role::Editor).await,
+            Err(Error::Forbidden)
+        ));
+        assert!(rooms.list_rooms("daniel").await.unwrap().contains(&room));
+
+        rooms.set_member(id, "daniel", Role::Editor).await.unwrap();
+        rooms.authorize(id, "daniel", Role::Editor).await.unwrap();
+        assert!(matches!(
+            rooms.authorize(id, "daniel", Role::Owner).await,
+            Err(Error::Forbidden)
+        ));
+        let members = rooms.list_members(id).await.unwrap();
+        assert_eq!(members.len(), 1);
+        assert_eq!(
+            (members[0].user_id.as_str(), members[0].role),
+            ("daniel", Role::Editor)
+        );
+
+        assert!(matches!(
+            rooms.set_member(id, "mara", Role::Viewer).await,
+            Err(Error::InvalidArgument(_))
+        ));
+
+        rooms.remove_member(id, "daniel").await.unwrap();
+        assert_eq!(rooms.role(id, "daniel").await.unwrap(), None);
+        assert!(matches!(
+            rooms.remove_member(id, "daniel").await,
+            Err(Error::NotFound)
+        ));
+
+        // A room without an owner grants nothing by itself.
+        assert_eq!(rooms.role(ROOM, "daniel").await.unwrap(), None);
+        rooms
+            .set_member(ROOM, "daniel", Role::Viewer)
+            .await
+            .unwrap();
+        assert_eq!(
+            rooms.role(ROOM, "daniel").await.unwrap(),
+            Some(Role::Viewer)
+        );
+        assert!(matches!(
+            rooms.role("missing", "daniel").await,
+            Err(Error::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn delete_room_closes_live_room_and_drops_its_edits() {
+        let (rooms, storage) = manager("delete", EVICT_RIGHT_AWAY).await;
+        rooms.set_member(ROOM, "mara", Role::Editor).await.unwrap();
+
+        let room = rooms.connect(ROOM).await.unwrap();
+        type_text(&room, "INT. HOUSE - DAY\n").await;
+
+        // Stands in for a websocket peer that keeps typing until it is told to close.
+        let peer = tokio::spawn(async move {
+            assert_eq!(
+                room.close_requested("mara", Role::Editor).await,
+                CloseReason::Deleted
+            );
+            type_text(&room, "Too late.").await;
+            room.release().await;
+        });
+
+        rooms.delete_room(ROOM).await.unwrap();
+        peer.await.unwrap();
+        assert!(!storage.room_exists(ROOM).await.unwrap());
+        assert!(matches!(
+            rooms.delete_room(ROOM).await,
+            Err(Error::NotFound)
+        ));
+        assert!(matches!(rooms.connect(ROOM).await, Err(Error::NotFound)));
+
+        // Nothing of the old room comes back with a new one under the same id.
+        storage
+            .create_room(ROOM, CreateRoomOptions::default())
+            .await
+            .unwrap();
+        let room = rooms.connect(ROOM).await.unwrap();
+        assert_eq!(text(&room).await, "");
+        room.release().await;
+        assert_eq!(
+            storage.get_room_info(ROOM).await.unwrap().unwrap().last_seq,
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn viewer_cannot_change_the_log() {
+        use yrs::sync::{Awareness, Protocol};
+        use yrs::updates::decoder::Decode;
+
+        let (rooms, storage) = manager("viewer", EVICT_RIGHT_AWAY).await;
+        let room = rooms.connect(ROOM).await.unwrap();
+        type_text(&room, "INT. HOUSE - DAY\n").await;
+        room.release().await;
+        let last_seq = storage.get_room_info(ROOM).await.unwrap().unwrap().last_seq;
+
+        let room = rooms.connect(ROOM).await.unwrap();
+        let viewer = Awareness::new(yrs::Doc::with_client_id(99));
+        let state = room
+            .awareness
+            .read()
+            .await
+            .doc()
+            .transact()
+            .encode_state_as_update_v1(&yrs::StateVector::default());
+        viewer
+            .doc()
+            .transact_mut()
+            .apply_update(yrs::Update::decode_v1(&state).unwrap());
+        let edit = {
+            let text = viewer.doc().get_or_insert_text("codemirror");
+            let mut txn = viewer.doc().transact_mut();
+            let before = txn.state_vector();
+            text.push(&mut txn, "Too bad.");
+            txn.encode_state_as_update_v1(&before)
+        };
+
+        let (protocol, mut clients) = crate::ws::protocol::PeerProtocol::new("daniel".into(), true);
+        {
+            let mut awareness = room.awareness.write().await;
+            let update = || yrs::Update::decode_v1(&edit).unwrap();
+            assert!(
+                protocol
+                    .handle_sync_step2(&mut awareness, update())
+                    .unwrap()
+                    .is_none()
+            );
+            assert!(
+                protocol
+                    .handle_update(&mut awareness, update())
+                    .unwrap()
+                    .is_none()
+            );
+
+            // The viewer still shows up to everyone else.
+            let mut presence = Awareness::new(viewer.doc().clone());
+            presence.set_local_state(r#"{"user":"daniel"}"#);
+            protocol
+                .handle_awareness_update(&mut awareness, presence.update().unwrap())
+                .unwrap();
+            assert!(awareness.clients().contains_key(&99));
+        }
+        assert!(clients.try_recv().is_err());
+        assert_eq!(text(&room).await, "INT. HOUSE - DAY\n");
+        room.release().await;
+
+        let info = storage.get_room_info(ROOM).await.unwrap().unwrap();
+        assert_eq!(info.last_seq, last_seq);
+    }
+
+    #[tokio::test]
+    async fn connections_claim_only_their_own_client() {
+        use yrs::sync::{Awareness, Protocol};
+        use yrs::updates::decoder::Decode;
+
+        /// Append `chunk` to the text of `doc` and return the update it produced.
+        fn edit(doc: &yrs::Doc, chunk: &str) -> Vec<u8> {
+            let text = doc.get_or_insert_text("codemirror");
+            let mut txn = doc.transact_mut();
+            let before = txn.state_vector();
+            text.push(&mut txn, chunk);
+            txn.encode_state_as_update_v1(&before)
+        }
+
+        let (rooms, _) = manager("claim", EVICT_RIGHT_AWAY).await;
+        let room = rooms.connect(ROOM).await.unwrap();
+
+        // Mara edited offline and her edit reached Daniel before the server.
+        let mara = yrs::Doc::with_client_id(5);
+        let daniel = Awareness::new(yrs::Doc::with_client_id(42));
+        let offline = edit(&mara, "INT. HOUSE - DAY\n");
+        daniel
+            .doc()
+            .transact_mut()
+            .apply_update(yrs::Update::decode_v1(&offline).unwrap());
+        edit(daniel.doc(), "A door slams.\n");
+        let state = daniel
+            .doc()
+            .transact()
+            .encode_state_as_update_v1(&yrs::StateVector::default());
+
+        let (protocol, mut clients) =
+            crate::ws::protocol::PeerProtocol::new("daniel".into(), false);
+        {
+            let mut awareness = room.awareness.write().await;
+            protocol
+                .handle_sync_step2(&mut awareness, yrs::Update::decode_v1(&state).unwrap())
+                .unwrap();
+        }
+        // Both clients are new, so the update does not tell which one is Daniel's.
+        assert!(clients.try_recv().is_err());
+
+        {
+            let mut awareness = room.awareness.write().await;
+            let mut presence = Awareness::new(daniel.doc().clone());
+            presence.set_local_state(r#"{"user":"daniel"}"#);
+            protocol
+                .handle_awareness_update(&mut awareness, presence.update().unwrap())
+                .unwrap();
+
+            let update = edit(daniel.doc(), "Silence.\n");
+            protocol
+                .handle_update(&mut awareness, yrs::Update::decode_v1(&update).unwrap())
+                .unwrap();
+        }
+        assert_eq!(clients.try_recv().unwrap(), 42);
+        assert!(clients.try_recv().is_err());
+
+        assert!(room.record_client(42, "daniel").await.unwrap());
+        assert!(!room.record_client(42, "mallory").await.unwrap());
+        room.release().await;
+    }
+
+    #[tokio::test]
+    async fn share_links_grant_their_role_until_revoked() {
+        let (rooms, _) = manager("share", EVICT_RIGHT_AWAY).await;
+        let room = rooms.create_room("mara", None, None).await.unwrap();
+        let id = room.room_id.as_str();
+
+        assert!(matches!(
+            rooms
+                .create_share(id, Role::Owner, "mara", None, None)
+                .await,
+            Err(Error::InvalidArgument(_))
+        ));
+        let share = rooms
+            .create_share(id, Role::Editor, "mara", None, Some(2))
+            .await
+            .unwrap();
+
+        rooms.redeem_share(&share.token, "daniel").await.unwrap();
+        assert_eq!(rooms.role(id, "daniel").await.unwrap(), Some(Role::Editor));
+
+        // The owner keeps their role, but the visit still counts.
+        rooms.redeem_share(&share.token, "mara").await.unwrap();
+        assert_eq!(rooms.role(id, "mara").await.unwrap(), Some(Role::Owner));
+        assert!(matches!(
+            rooms.redeem_share(&share.token, "guest|x").await,
+            Err(Error::NotFound)
+        ));
+
+        rooms.delete_share(id, &share.share_id).await.unwrap();
+        assert_eq!(rooms.role(id, "daniel").await.unwrap(), None);
+
+        // A role granted directly outlives the share, even a lower one.
+        rooms.set_member(id, "daniel", Role::Viewer).await.unwrap();
+        let share = rooms
+            .create_share(id, Role::Editor, "mara", None, None)
+            .await
+            .unwrap();
+        rooms.redeem_share(&share.token, "daniel").await.unwrap();
+        assert_eq!(rooms.role(id, "daniel").await.unwrap(), Some(Role::Viewer));
+        rooms.delete_share(id, &share.share_id).await.unwrap();
+        assert_eq!(rooms.role(id, "daniel").await.unwrap(), Some(Role::Viewer));
+    }
+
+    #[tokio::test]
+    async fn revoking_access_closes_open_connections() {
+        let (rooms, _) = manager("revoke", EVICT_RIGHT_AWAY).await;
+        let room = rooms.create_room("mara", None, None).await.unwrap();
+        let id = room.room_id.clone();
+        let share = rooms
+            .create_share(&id, Role::Editor, "mara", None, None)
+            .await
+            .unwrap();
+        rooms.redeem_share(&share.token, "daniel").await.unwrap();
+        rooms.set_member(&id, "lena", Role::Editor).await.unwrap();
+
+        // Each stands in for a websocket peer.
+        let connect = |user_id: &'static str, role| {
+            let rooms = rooms.clone();
+            let id = id.clone();
+            tokio::spawn(async move {
+                let room = rooms.connect(&id).await.unwrap();
+                let reason = room.close_requested(user_id, role).await;
+                room.release().await;
+                reason
+            })
+        };
+        let owner = connect("mara", Role::Owner);
+        let shared = connect("daniel", Role::Editor);
+        let member = connect("lena", Role::Editor);
+
+        rooms.delete_share(&id, &share.share_id).await.unwrap();
+        assert_eq!(shared.await.unwrap(), CloseReason::Revoked);
+
+        rooms.set_member(&id, "lena", Role::Viewer).await.unwrap();
+        assert_eq!(member.await.unwrap(), CloseReason::RoleChanged);
+
+        // Connections whose role stayed the same are kept.
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!owner.is_finished());
+        rooms.shutdown(Duration::from_secs(5)).await;
+        assert_eq!(owner.await.unwrap(), CloseReason::ShuttingDown);
+    }
+
+    #[tokio::test]
+    async fn revoked_client_ignoring_the_close_frame_cannot_edit() {
+        use futures_util::SinkExt;
+        use tokio_tungstenite::tungstenite;
+        use yrs::sync::{Message, SyncMessage};
+        use yrs::updates::encoder::Encode;
+
+        let (rooms, _) = manager("ignore-close", EVICT_RIGHT_AWAY).await;
+        let id = rooms.create_room("mara", None, None).await.unwrap().room_id;
+        rooms.set_member(&id, "daniel", Role::Editor).await.unwrap();
+
+        // Serves Daniel's websocket like the real handler, once he is authorized.
+        let app = axum::Router::new().route(
+            "/",
+            axum::routing::get({
+                let rooms = rooms.clone();
+                let id = id.clone();
+                move |ws: axum::extract::WebSocketUpgrade| async move {
+                    let room = rooms.connect(&id).await.unwrap();
+                    ws.on_upgrade(move |socket| {
+                        crate::ws::peer::peer(socket, room, "daniel".into(), Role::Editor)
+                    })
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let room = rooms.connect(&id).await.unwrap();
+        let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{addr}/"))
+            .await
+            .unwrap();
+        let daniel = yrs::Doc::new();
+        let mut send = async |chunk: &str| {
+            let text = daniel.get_or_insert_text("codemirror");
+            let update = {
+                let mut txn = daniel.transact_mut();
+                let before = txn.state_vector();
+                text.push(&mut txn, chunk);
+                txn.encode_state_as_update_v1(&before)
+            };
+            let msg = Message::Sync(SyncMessage::Update(update)).encode_v1();
+            client
+                .send(tungstenite::Message::Binary(msg.into()))
+                .await
+                .unwrap();
+        };
+
+        send("INT. HOUSE - DAY\n").await;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while text(&room).await.is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        // The client never reads the close frame, so it doesn't answer it either.
+        rooms.remove_member(&id, "daniel").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        send("Too late.").await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(text(&room).await, "INT. HOUSE - DAY\n");
+        room.release().await;
+    }
+
+    #[tokio::test]
+    async fn first_user_to_open_the_demo_room_owns_it() {
+        let (rooms, _) = manager("demo", EVICT_RIGHT_AWAY).await;
+        let demo = "demo-room-1";
+
+        // Guests can't claim it.
+        assert!(matches!(
+            rooms.authorize(demo, "guest|x", Role::Viewer).await,
+            Err(Error::NotFound)
+        ));
+
+        assert_eq!(
+            rooms.authorize(demo, "daniel", Role::Editor).await.unwrap(),
+            Role::Owner
+        );
+        let room = rooms.connect(demo).await.unwrap();
+        assert!(text(&room).await.starts_with("EXT. BRICK'S PATIO - DAY"));
+        room.release().await;
+
+        // From then on it is shared like any other room.
+        assert!(matches!(
+            rooms.authorize(demo, "mara", Role::Viewer).await,
+            Err(Error::NotFound)
+        ));
+        rooms.set_member(demo, "mara", Role::Viewer).await.unwrap();
+        assert_eq!(
+            rooms.authorize(demo, "mara", Role::Viewer).await.unwrap(),
+            Role::Viewer
+        );
+    }
+
+    #[tokio::test]
+    async fn guests_hold_only_what_was_shared_with_them() {
+        let (rooms, _) = manager("guest", EVICT_RIGHT_AWAY).await;
+        let guest = "guest|x";
+
+        // Like the demo room, ROOM has no owner.
+        assert_eq!(rooms.role(ROOM, guest).await.unwrap(), None);
+        assert!(rooms.list_rooms(guest).await.unwrap().is_empty());
+        assert!(matches!(
+            rooms.set_member(ROOM, guest, Role::Editor).await,
+            Err(Error::InvalidArgument(_))
+        ));
+
+        let share = rooms
+            .create_share(ROOM, Role::Viewer, "mara", None, None)
+            .await
+            .unwrap();
+        rooms.redeem_share(&share.token, guest).await.unwrap();
+        assert_eq!(rooms.role(ROOM, guest).await.unwrap(), Some(Role::Viewer));
+        let listed: Vec<String> = rooms
+            .list_rooms(guest)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.room_id)
+            .collect();
+        assert_eq!(listed, [ROOM]);
+    }
+}
@@ -0,0 +1,65 @@
+//! Bringing the work done in one room into another room it shares history with.
+//!
+//! A fork, or a room seeded with a copy of another room's log, holds the same yrs items as its
+//! parent up to the copy point. What it changed since is everything it has that the parent's
+//! state vector does not cover, and applying that to the parent merges the two like any pair of
+//! concurrent edits.
+use serde::Serialize;
+use yrs::updates::decoder::Decode;
+use yrs::{Doc, ReadTxn, StateVector, Transact, Update};
+
+use crate::rooms::error::Error;
+use crate::rooms::history;
+use crate::rooms::persistence::authored_by;
+
+/// The outcome of a merge, or what it would be for a dry run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Merge {
+    /// The Fountain text of the target after the merge.
+    pub text: String,
+    /// Whether the merge changes the text of the target.
+    pub changed: bool,
+}
+
+/// The changes of `source` that `target` is missing.
+///
+/// Fails with [`Error::InvalidArgument`] if neither is empty and they share no history, as their
+/// texts would then be spliced together rather than merged.
+pub fn missing_changes(target: &Doc, source: &Doc) -> Result<Vec<u8>, Error> {
+    let target_sv = target.transact().state_vector();
+    let source_sv = source.transact().state_vector();
+
+    let shared = target_sv
+        .iter()
+        .any(|(client, _)| source_sv.get(client) > 0);
+    if !shared && !target_sv.is_empty() && !source_sv.is_empty() {
+        return Err(Error::InvalidArgument(
+            "the rooms share no history".to_string(),
+        ));
+    }
+
+    Ok(source.transact().encode_state_as_update_v1(&target_sv))
+}
+
+/// Apply `changes` from [`missing_changes`] to `target` on behalf of `author`.
+pub fn apply(target: &Doc, changes: &[u8], author: &str) -> Result<(), Error> {
+    let update = Update::decode_v1(changes)?;
+    target
+        .transact_mut_with(authored_by(author))
+        .apply_update(update);
+    Ok(())
+}
+
+/// The text `target` would have with `changes` applied, leaving `target` untouched.
+pub fn preview(target: &Doc, changes: &[u8]) -> Result<String, Error> {
+    let copy = Doc::new();
+    {
+        let state = target
+            .transact()
+            .encode_state_as_update_v1(&StateVector::default());
+        let mut txn = copy.transact_mut();
+        txn.apply_update(Update::decode_v1(&state)?);
+        txn.apply_update(Update::decode_v1(changes)?);
+    }
+    Ok(history::text(&copy))
+}
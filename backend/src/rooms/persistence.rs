@@ -0,0 +1,628 @@
+//! Writes the updates of a live room to [`Storage`].
+//!
+//! Updates are handed from the document observer to a per-room task through a bounded queue.
+//! The observer runs inside a yrs transaction and cannot wait, so when the queue is full the
+//! room is marked dirty instead of blocking. The task then appends the full document state as a
+//! regular update, which covers everything that did not fit, and snapshots it.
+//!
+//! When storage is unavailable updates are written to the room's [`Journal`] instead and the
+//! task retries with exponential backoff, replaying the journal in order once storage is back.
+//! Should the journal fill up it is dropped in favour of a full state resync. Until everything
+//! has reached storage the room reports itself unhealthy through [`PersistenceStatus`].
+//!
+//! Once the room is evicted the task writes out what is left in the queue and takes a final
+//! snapshot, so the next load starts from a complete log. If storage is still unavailable at that
+//! point the remaining updates stay in the journal, which is replayed when the room is loaded
+//! again.
+//!
+//! To avoid one storage transaction per keystroke the task collects updates for a short window
+//! and writes them with a single [`Storage::append_updates`], optionally merged into one update
+//! per author.
+//!
+//! The author of an update is taken from the origin of the transaction that made it, see
+//! [`authored_by`]. Changes without one, like full state resyncs, are stored without an author.
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::watch;
+use tokio::time::{Duration, Instant};
+use yrs::updates::decoder::Decode;
+use yrs::{Doc, Origin, ReadTxn, Subscription, Transact, TransactionMut, Update};
+use yrs_axum::AwarenessRef;
+
+use crate::config::{Batching, Retry, SnapshotRetention, UpdateRetention};
+use crate::rooms::error::Error;
+use crate::rooms::journal::Journal;
+use crate::rooms::retention;
+use crate::rooms::storage::{LogSeq, NewUpdate, Snapshot, Storage};
+
+/// Counters describing the persistence of all rooms since startup.
+#[derive(Debug, Default)]
+pub struct PersistenceMetrics {
+    /// Updates appended to storage, including full state resyncs. Merged updates count
+    /// individually.
+    pub updates_persisted: AtomicU64,
+    /// Calls to [`Storage::append_updates`].
+    pub batches_written: AtomicU64,
+    /// Updates that did not fit in a room's queue.
+    pub updates_overflowed: AtomicU64,
+    /// Failed attempts to append to storage.
+    pub append_failures: AtomicU64,
+    /// Full document states appended to recover from overflow or failures.
+    pub full_state_resyncs: AtomicU64,
+    /// Updates written to a room's journal because storage was unavailable.
+    pub updates_journaled: AtomicU64,
+    /// Journals replayed into storage after it recovered.
+    pub journal_replays: AtomicU64,
+    pub snapshots_stored: AtomicU64,
+    pub snapshot_failures: AtomicU64,
+}
+
+impl PersistenceMetrics {
+    fn inc(counter: &AtomicU64) {
+        Self::add(counter, 1);
+    }
+
+    fn add(counter: &AtomicU64, n: usize) {
+        counter.fetch_add(n as u64, Ordering::Relaxed);
+    }
+}
+
+/// How a room is persisted. Shared by every room of a [`RoomManager`](super::RoomManager).
+#[derive(Clone)]
+pub struct PersistenceSettings {
+    pub queue_capacity: usize,
+    pub snapshot_every_n_updates: u64,
+    pub batching: Batching,
+    pub update_retention: UpdateRetention,
+    pub snapshot_retention: SnapshotRetention,
+    pub retry: Retry,
+    pub journal: crate::config::Journal,
+}
+
+/// Whether the updates of a room have reached storage.
+#[derive(Debug, Default)]
+pub struct PersistenceStatus {
+    /// Set when updates were dropped and the log needs a full state resync.
+    dirty: AtomicBool,
+    /// Bytes in the room's journal waiting to be replayed.
+    journaled_bytes: AtomicU64,
+    /// Set once the persistence task has finished.
+    stopped: watch::Sender<bool>,
+    /// Set when the room is deleted and nothing more should be written for it.
+    discarded: AtomicBool,
+}
+
+impl PersistenceStatus {
+    /// A room is healthy when every update applied to it has been written to storage, apart
+    /// from those still queued or being batched.
+    pub fn is_healthy(&self) -> bool {
+        !self.dirty.load(Ordering::Acquire) && self.journaled_bytes() == 0
+    }
+
+    pub fn journaled_bytes(&self) -> u64 {
+        self.journaled_bytes.load(Ordering::Acquire)
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        *self.stopped.borrow()
+    }
+
+    /// Stop writing the updates of the room anywhere, dropping those that have not reached
+    /// storage yet. For a room that is being deleted.
+    pub fn discard(&self) {
+        self.discarded.store(true, Ordering::Release);
+    }
+
+    fn is_discarded(&self) -> bool {
+        self.discarded.load(Ordering::Acquire)
+    }
+
+    /// Wait until the persistence task has written out everything and finished.
+    pub async fn stopped(&self) {
+        // The sender lives in `self`, so this can not fail.
+        let _ = self.stopped.subscribe().wait_for(|stopped| *stopped).await;
+    }
+}
+
+/// Transaction origin recording `user_id` as the author of the changes made in it.
+pub fn authored_by(user_id: &str) -> Origin {
+    Origin::from(user_id)
+}
+
+/// The author recorded by [`authored_by`], if any.
+fn author(txn: &TransactionMut) -> Option<String> {
+    txn.origin()
+        .map(|origin| String::from_utf8_lossy(origin.as_ref()).into_owned())
+}
+
+/// Start persisting the updates of `doc`.
+///
+/// `awareness` must wrap `doc`, it is used to read the full document state without racing
+/// writers. `stored` is the document as it is in storage, snapshots are taken from it so they
+/// hold exactly what the log holds. Entries already in `journal` are replayed first, they must
+/// have been applied to `doc` but not to `stored`. Persistence stops once the returned
+/// [`Subscription`] is dropped and the queue is drained, see [`PersistenceStatus::stopped`].
+#[allow(clippy::too_many_arguments)]
+pub fn spawn(
+    storage: Arc<dyn Storage>,
+    room_id: &str,
+    doc: &Doc,
+    stored: Doc,
+    awareness: AwarenessRef,
+    journal: Journal,
+    settings: PersistenceSettings,
+    metrics: Arc<PersistenceMetrics>,
+) -> (Subscription, Arc<PersistenceStatus>) {
+    let (tx, rx) = mpsc::channel::<NewUpdate>(settings.queue_capacity);
+    let status = Arc::new(PersistenceStatus::default());
+    status
+        .journaled_bytes
+        .store(journal.len(), Ordering::Release);
+
+    let task = PersistTask {
+        storage,
+        room_id: room_id.to_string(),
+        awareness,
+        stored,
+        backoff: Duration::from_millis(settings.retry.initial_backoff_ms),
+        next_retry: Instant::now(),
+        settings,
+        metrics: metrics.clone(),
+        status: status.clone(),
+        journal,
+        since_snapshot: 0,
+        last_seq: None,
+    };
+    tokio::spawn(task.run(rx));
+
+    let room_id = room_id.to_string();
+    let dirty = status.clone();
+    let sub = doc
+        .observe_update_v1(move |txn, e| {
+            let update = NewUpdate {
+                bytes: e.update.clone(),
+                author: author(txn),
+            };
+            match tx.try_send(update) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    PersistenceMetrics::inc(&metrics.updates_overflowed);
+                    if !dirty.dirty.swap(true, Ordering::AcqRel) {
+                        tracing::warn!(
+                            room_id,
+                            "persistence queue full, falling back to a full state resync"
+                        );
+                    }
+                }
+                // The task only stops once this observer is gone.
+                Err(TrySendError::Closed(_)) => {}
+            }
+        })
+        .expect("Subscription function should work.");
+
+    (sub, status)
+}
+
+struct PersistTask {
+    storage: Arc<dyn Storage>,
+    room_id: String,
+    awareness: AwarenessRef,
+    /// The document as far as it has been appended to storage. The live document may be ahead
+    /// with updates still queued, batched or journaled.
+    stored: Doc,
+    settings: PersistenceSettings,
+    metrics: Arc<PersistenceMetrics>,
+    status: Arc<PersistenceStatus>,
+    /// Updates that failed to reach storage, in order.
+    journal: Journal,
+    /// Delay after the next failed recovery attempt.
+    backoff: Duration,
+    next_retry: Instant,
+    since_snapshot: u64,
+    /// Last sequence number this task appended.
+    last_seq: Option<LogSeq>,
+}
+
+impl PersistTask {
+    async fn run(mut self, mut rx: mpsc::Receiver<NewUpdate>) {
+        loop {
+            if self.status.is_discarded() {
+                self.drop_pending().await;
+            }
+            let update = if self.has_pending() {
+                tokio::select! {
+                    update = rx.recv() => update,
+                    () = tokio::time::sleep_until(self.next_retry) => {
+                        self.recover().await;
+                        continue;
+                    }
+                }
+            } else {
+                rx.recv().await
+            };
+            let Some(update) = update else { break };
+
+            let batch = self.collect_batch(update, &mut rx).await;
+            self.persist(batch).await;
+        }
+
+        self.finish().await;
+        self.status.stopped.send_replace(true);
+    }
+
+    /// Write out what is left once the room is gone and take a final snapshot.
+    async fn finish(&mut self) {
+        if self.status.is_discarded() {
+            self.drop_pending().await;
+            return;
+        }
+        if self.has_pending() {
+            self.recover().await;
+        }
+
+        // Rather than holding up the next load until storage is back, leave everything in the
+        // journal. The next load of the room applies and replays it.
+        while self.has_pending() {
+            if self.park().await {
+                tracing::warn!(
+                    room_id = self.room_id,
+                    journaled_bytes = self.journal.len(),
+                    "room evicted with updates left in the journal"
+                );
+                return;
+            }
+            tokio::time::sleep_until(self.next_retry).await;
+            self.recover().await;
+        }
+
+        if self.since_snapshot > 0
+            && let Some(seq) = self.last_seq
+        {
+            self.snapshot(seq).await;
+        }
+    }
+
+    /// Make sure every update that did not reach storage is in the journal.
+    async fn park(&mut self) -> bool {
+        if !self.status.dirty.load(Ordering::Acquire) {
+            return true;
+        }
+
+        // The full document state covers whatever is journaled already.
+        let bytes = self.encode_state().await;
+        let parked = match self.journal.clear().await {
+            Ok(()) => {
+                let state = NewUpdate {
+                    bytes,
+                    author: None,
+                };
+                self.journal.append(&[state]).await
+            }
+            Err(e) => Err(e),
+        };
+        self.sync_journal_status();
+
+        match parked {
+            Ok(()) => {
+                self.status.dirty.store(false, Ordering::Release);
+                true
+            }
+            Err(e) => {
+                tracing::error!(room_id = self.room_id, error = ?e, "journaling full state failed");
+                self.schedule_retry();
+                false
+            }
+        }
+    }
+
+    /// Forget every update that has not reached storage, including the journal.
+    async fn drop_pending(&mut self) {
+        if let Err(e) = self.journal.clear().await {
+            tracing::error!(room_id = self.room_id, error = ?e, "clearing journal failed");
+        }
+        self.status.dirty.store(false, Ordering::Release);
+        self.sync_journal_status();
+    }
+
+    /// Whether some updates are only in memory or in the journal.
+    fn has_pending(&self) -> bool {
+        !self.journal.is_empty() || self.status.dirty.load(Ordering::Acquire)
+    }
+
+    /// Collect updates following `first` until the batch window closes or the batch is full.
+    async fn collect_batch(
+        &self,
+        first: NewUpdate,
+        rx: &mut mpsc::Receiver<NewUpdate>,
+    ) -> Vec<NewUpdate> {
+        let mut batch = vec![first];
+        let deadline = Instant::now() + Duration::from_millis(self.settings.batching.window_ms);
+
+        while batch.len() < self.settings.batching.max_updates {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Some(update)) => batch.push(update),
+                // Window closed, or the room is gone and this is the last batch.
+                Ok(None) | Err(_) => break,
+            }
+        }
+
+        batch
+    }
+
+    async fn persist(&mut self, batch: Vec<NewUpdate>) {
+        if self.status.is_discarded() {
+            return;
+        }
+        let count = batch.len();
+        let entries = self.merge(batch);
+
+        // Earlier updates are still waiting in the journal, keep the order.
+        if !self.journal.is_empty() {
+            self.journal(&entries, count).await;
+            return;
+        }
+
+        match self.storage.append_updates(&self.room_id, &entries).await {
+            Ok((first, last)) => {
+                PersistenceMetrics::add(&self.metrics.updates_persisted, count);
+                PersistenceMetrics::inc(&self.metrics.batches_written);
+                self.since_snapshot += last - first + 1;
+                self.last_seq = Some(last);
+                self.apply_stored(&entries);
+
+                if self.since_snapshot >= self.settings.snapshot_every_n_updates {
+                    self.snapshot(last).await;
+                }
+            }
+            Err(e) => {
+                PersistenceMetrics::inc(&self.metrics.append_failures);
+                tracing::error!(room_id = self.room_id, count, error = ?e, "append_updates failed");
+                self.journal(&entries, count).await;
+                self.schedule_retry();
+            }
+        }
+    }
+
+    /// Keep `entries` in the journal until storage is available again.
+    async fn journal(&mut self, entries: &[NewUpdate], count: usize) {
+        match self.journal.append(entries).await {
+            Ok(()) => PersistenceMetrics::add(&self.metrics.updates_journaled, count),
+            Err(e) => {
+                // The full document state covers the journal as well as these entries.
+                tracing::error!(
+                    room_id = self.room_id,
+                    error = ?e,
+                    "journal unavailable, falling back to a full state resync"
+                );
+                self.status.dirty.store(true, Ordering::Release);
+                if let Err(e) = self.journal.clear().await {
+                    tracing::error!(room_id = self.room_id, error = ?e, "clearing journal failed");
+                }
+            }
+        }
+        self.sync_journal_status();
+    }
+
+    /// Replay the journal, then resync if needed. Backs off if either fails.
+    async fn recover(&mut self) {
+        if self.replay_journal().await && self.resync_if_dirty().await {
+            self.backoff = Duration::from_millis(self.settings.retry.initial_backoff_ms);
+        } else {
+            self.schedule_retry();
+        }
+    }
+
+    /// Schedule the next recovery attempt and back off further.
+    fn schedule_retry(&mut self) {
+        self.next_retry = Instant::now() + self.backoff;
+        let max = Duration::from_millis(self.settings.retry.max_backoff_ms);
+        self.backoff = (self.backoff * 2).min(max);
+    }
+
+    /// Append every journaled update to storage in order, as a single batch.
+    async fn replay_journal(&mut self) -> bool {
+        if self.journal.is_empty() {
+            return true;
+        }
+
+        let entries = match self.journal.read_all().await {
+            Ok(entries) => entries,
+            Err(e) => {
+                tracing::error!(room_id = self.room_id, error = ?e, "reading journal failed");
+                return false;
+            }
+        };
+
+        let last = if entries.is_empty() {
+            None
+        } else {
+            match self.storage.append_updates(&self.room_id, &entries).await {
+                Ok((first, last)) => {
+                    PersistenceMetrics::add(&self.metrics.updates_persisted, entries.len());
+                    PersistenceMetrics::inc(&self.metrics.batches_written);
+                    PersistenceMetrics::inc(&self.metrics.journal_replays);
+                    tracing::info!(room_id = self.room_id, first, last, "replayed journal");
+                    self.since_snapshot += last - first + 1;
+                    self.last_seq = Some(last);
+                    self.apply_stored(&entries);
+                    Some(last)
+                }
+                Err(e) => {
+                    PersistenceMetrics::inc(&self.metrics.append_failures);
+                    tracing::warn!(
+                        room_id = self.room_id,
+                        retry_in = ?self.backoff,
+                        error = ?e,
+                        "journal replay failed"
+                    );
+                    return false;
+                }
+            }
+        };
+
+        // The entries are in storage now. If clearing fails they are appended again on the
+        // next attempt, which is harmless as applying an update twice has no effect.
+        if let Err(e) = self.journal.clear().await {
+            tracing::error!(room_id = self.room_id, error = ?e, "clearing journal failed");
+            return false;
+        }
+        self.sync_journal_status();
+
+        if let Some(last) = last
+            && self.since_snapshot >= self.settings.snapshot_every_n_updates
+        {
+            self.snapshot(last).await;
+        }
+        true
+    }
+
+    fn sync_journal_status(&self) {
+        self.status
+            .journaled_bytes
+            .store(self.journal.len(), Ordering::Release);
+    }
+
+    /// Merge each run of updates by the same author into a single update if enabled, otherwise
+    /// keep the batch as is.
+    fn merge(&self, batch: Vec<NewUpdate>) -> Vec<NewUpdate> {
+        if !self.settings.batching.merge || batch.len() < 2 {
+            return batch;
+        }
+
+        let mut merged = Vec::new();
+        for run in batch.chunk_by(|a, b| a.author == b.author) {
+            if run.len() < 2 {
+                merged.extend_from_slice(run);
+                continue;
+            }
+
+            let updates: Vec<&[u8]> = run.iter().map(|u| u.bytes.as_slice()).collect();
+            match yrs::merge_updates_v1(&updates) {
+                Ok(bytes) => merged.push(NewUpdate {
+                    bytes,
+                    author: run[0].author.clone(),
+                }),
+                Err(e) => {
+                    tracing::warn!(room_id = self.room_id, error = ?e, "merging updates failed");
+                    merged.extend_from_slice(run);
+                }
+            }
+        }
+        merged
+    }
+
+    /// Append the full document state if updates were dropped, covering every one of them.
+    async fn resync_if_dirty(&mut self) -> bool {
+        if !self.status.dirty.swap(false, Ordering::AcqRel) {
+            return true;
+        }
+        let state = NewUpdate {
+            bytes: self.encode_state().await,
+            author: None,
+        };
+
+        match self
+            .storage
+            .append_update(&self.room_id, &state.bytes, None)
+            .await
+        {
+            Ok(seq) => {
+                PersistenceMetrics::inc(&self.metrics.updates_persisted);
+                PersistenceMetrics::inc(&self.metrics.full_state_resyncs);
+                tracing::warn!(room_id = self.room_id, seq, "resynced full document state");
+                self.last_seq = Some(seq);
+                self.apply_stored(&[state]);
+
+                self.snapshot(seq).await;
+                true
+            }
+            Err(e) => {
+                PersistenceMetrics::inc(&self.metrics.append_failures);
+                tracing::error!(room_id = self.room_id, error = ?e, "full state resync failed");
+                self.status.dirty.store(true, Ordering::Release);
+                false
+            }
+        }
+    }
+
+    /// Encode the full document state as an update (v1).
+    async fn encode_state(&self) -> Vec<u8> {
+        // Writers hold the awareness write lock while they transact.
+        let awareness = self.awareness.read().await;
+        awareness
+            .doc()
+            .transact()
+            .encode_state_as_update_v1(&yrs::StateVector::default())
+    }
+
+    /// Apply `entries`, which were just appended to storage, to the stored document.
+    fn apply_stored(&self, entries: &[NewUpdate]) {
+        let mut txn = self.stored.transact_mut();
+        for entry in entries {
+            match Update::decode_v1(&entry.bytes) {
+                Ok(update) => txn.apply_update(update),
+                // It came from the live document, so this does not happen. Should it, the next
+                // snapshot misses the update but the log still has it.
+                Err(e) => {
+                    tracing::error!(room_id = self.room_id, error = ?e, "decoding stored update failed")
+                }
+            }
+        }
+    }
+
+    /// Store the stored document as snapshot covering `seq`, the last appended update, and apply
+    /// retention once it is committed.
+    async fn snapshot(&mut self, seq: LogSeq) {
+        let snap = Snapshot {
+            covered_through: seq,
+            bytes: self
+                .stored
+                .transact()
+                .encode_state_as_update_v1(&yrs::StateVector::default()),
+        };
+
+        // Attempt to store snapshot. On error just log and continue.
+        if let Err(e) = self.storage.store_snapshot(&self.room_id, snap).await {
+            PersistenceMetrics::inc(&self.metrics.snapshot_failures);
+            tracing::error!(room_id = self.room_id, error = ?e, "snapshot failed");
+            return;
+        }
+        PersistenceMetrics::inc(&self.metrics.snapshots_stored);
+        self.since_snapshot = 0;
+
+        if let Err(e) = self.apply_retention(seq).await {
+            tracing::error!(room_id = self.room_id, error = ?e, "retention failed");
+        }
+    }
+
+    async fn apply_retention(&self, covered_through: LogSeq) -> Result<(), Error> {
+        let storage = &*self.storage;
+
+        // Snapshot is committed, so older updates are no longer needed.
+        let pruned = retention::prune_updates(
+            storage,
+            &self.room_id,
+            covered_through,
+            &self.settings.update_retention,
+        )
+        .await?;
+
+        let removed =
+            retention::gc_snapshots(storage, &self.room_id, &self.settings.snapshot_retention)
+                .await?;
+
+        if pruned > 0 || !removed.is_empty() {
+            let removed: Vec<LogSeq> = removed.iter().map(|s| s.covered_through).collect();
+            tracing::info!(
+                room_id = self.room_id,
+                pruned_updates = pruned,
+                removed_snapshots = ?removed,
+                "applied retention"
+            );
+        }
+
+        Ok(())
+    }
+}
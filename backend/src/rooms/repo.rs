@@ -1,11 +1,13 @@
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use sqlx::{Executor, Postgres, Transaction};
 
 use crate::db::Db;
 use crate::rooms::error::Error;
 use crate::rooms::storage::{
-    CreateRoomOptions, LoadUpdatesOptions, LogSeq, RoomInfo, Snapshot, SnapshotInfo, Storage,
-    UpdateEntry,
+    ClientInfo, CreateRoomOptions, ForkPoint, LoadUpdatesOptions, LogSeq, Member, NewShare,
+    NewUpdate, NewVersion, PruneUpdatesOptions, Role, RoomInfo, RoomMetadataUpdate, Share,
+    Snapshot, SnapshotInfo, Storage, UpdateEntry, Version,
 };
 
 impl From<sqlx::Error> for Error {
@@ -16,6 +18,21 @@ impl From<sqlx::Error> for Error {
     }
 }
 
+/// Like `Error::from`, but a foreign key violation means the room is missing.
+pub(super) fn missing_room_on_fk(value: sqlx::Error) -> Error {
+    match value.as_database_error() {
+        Some(e) if e.is_foreign_key_violation() => Error::NotFound,
+        _ => Error::from(value),
+    }
+}
+
+/// Reads a role as stored by [`Role::as_str`].
+pub(super) fn stored_role(s: &str) -> Result<Role, Error> {
+    Role::parse(s).ok_or_else(|| Error::Backend {
+        source: format!("unknown role {s:?}").into(),
+    })
+}
+
 #[derive(Clone)]
 pub struct DatabaseStorage {
     db: Db,
@@ -24,6 +41,7 @@ pub struct DatabaseStorage {
 impl DatabaseStorage {
     pub async fn new(db: Db) -> Self {
         let storage = Self { db };
+        // Without an owner, the demo room is claimed by the first user to open it.
         storage
             .create_room(
                 "demo-room-1",
@@ -51,7 +69,8 @@ impl DatabaseStorage {
             UPDATE
                 rooms
             SET
-                last_seq = last_seq + $2
+                last_seq = last_seq + $2,
+                updated_at = now()
             WHERE
                 room_id = $1
             RETURNING (last_seq - $2 + 1) AS "first_seq!",
@@ -95,11 +114,17 @@ impl Storage for DatabaseStorage {
     async fn create_room(&self, room_id: &str, opts: CreateRoomOptions) -> Result<(), Error> {
         let res = sqlx::query!(
             r#"
-            INSERT INTO rooms (room_id, last_seq)
-                VALUES ($1, 0)
+            INSERT INTO rooms (room_id, last_seq, parent_room_id, forked_at_seq, title, owner,
+                description)
+                VALUES ($1, 0, $2, $3, $4, $5, $6)
             ON CONFLICT (room_id)
                 DO NOTHING"#,
-            room_id
+            room_id,
+            opts.fork.as_ref().map(|f| f.parent.as_str()),
+            opts.fork.as_ref().map(|f| f.seq as i64),
+            opts.title,
+            opts.owner,
+            opts.description
         )
         .execute(self.db.pool())
         .await
@@ -111,6 +136,59 @@ impl Storage for DatabaseStorage {
         Ok(())
     }
 
+    async fn update_room_metadata(
+        &self,
+        room_id: &str,
+        update: RoomMetadataUpdate,
+    ) -> Result<(), Error> {
+        let res = sqlx::query!(
+            r#"
+            UPDATE
+                rooms
+            SET
+                title = coalesce($2, title),
+                owner = coalesce($3, owner),
+                description = coalesce($4, description)
+            WHERE
+                room_id = $1"#,
+            room_id,
+            update.title,
+            update.owner,
+            update.description
+        )
+        .execute(self.db.pool())
+        .await
+        .map_err(Error::from)?;
+
+        if res.rows_affected() == 0 {
+            return Err(Error::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn claim_room(&self, room_id: &str, owner: &str) -> Result<bool, Error> {
+        let res = sqlx::query!(
+            r#"
+            UPDATE
+                rooms
+            SET
+                owner = $2
+            WHERE
+                room_id = $1
+                AND owner IS NULL"#,
+            room_id,
+            owner
+        )
+        .execute(self.db.pool())
+        .await
+        .map_err(Error::from)?;
+
+        if res.rows_affected() == 0 && !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+        Ok(res.rows_affected() > 0)
+    }
+
     async fn delete_room(&self, room_id: &str) -> Result<(), Error> {
         // Assumes FK ON DELETE CASCADE to updates/snapshots.
         sqlx::query!(
@@ -130,14 +208,23 @@ impl Storage for DatabaseStorage {
             SELECT
                 r.room_id,
                 r.last_seq,
+                r.parent_room_id,
+                r.forked_at_seq,
+                r.title,
+                r.owner,
+                r.description,
+                r.created_at,
+                r.updated_at,
                 s.covered_through AS "snap_covered?",
-                s.size_bytes AS snap_size
+                s.size_bytes AS snap_size,
+                s.created_at AS "snap_created_at?"
             FROM
                 rooms r
                 LEFT JOIN LATERAL (
                     SELECT
                         covered_through,
-                        octet_length(bytes)::bigint AS size_bytes
+                        octet_length(bytes)::bigint AS size_bytes,
+                        created_at
                     FROM
                         room_snapshots
                     WHERE
@@ -157,10 +244,26 @@ impl Storage for DatabaseStorage {
             .map(|r| RoomInfo {
                 room_id: r.room_id,
                 last_seq: r.last_seq as u64,
-                latest_snapshot: r.snap_covered.map(|ct| SnapshotInfo {
-                    covered_through: ct as u64,
-                    size_bytes: r.snap_size.unwrap_or(0) as u64,
-                }),
+                latest_snapshot: r
+                    .snap_covered
+                    .zip(r.snap_created_at)
+                    .map(|(ct, created_at)| SnapshotInfo {
+                        covered_through: ct as u64,
+                        size_bytes: r.snap_size.unwrap_or(0) as u64,
+                        created_at,
+                    }),
+                fork: r
+                    .parent_room_id
+                    .zip(r.forked_at_seq)
+                    .map(|(parent, seq)| ForkPoint {
+                        parent,
+                        seq: seq as u64,
+                    }),
+                title: r.title,
+                owner: r.owner,
+                description: r.description,
+                created_at: r.created_at,
+                updated_at: r.updated_at,
             })
             .collect())
     }
@@ -171,14 +274,23 @@ impl Storage for DatabaseStorage {
             SELECT
                 r.room_id,
                 r.last_seq,
+                r.parent_room_id,
+                r.forked_at_seq,
+                r.title,
+                r.owner,
+                r.description,
+                r.created_at,
+                r.updated_at,
                 s.covered_through AS "snap_covered?",
-                s.size_bytes AS snap_size
+                s.size_bytes AS snap_size,
+                s.created_at AS "snap_created_at?"
             FROM
                 rooms r
                 LEFT JOIN LATERAL (
                     SELECT
                         covered_through,
-                        octet_length(bytes)::bigint AS size_bytes
+                        octet_length(bytes)::bigint AS size_bytes,
+                        created_at
                     FROM
                         room_snapshots
                     WHERE
@@ -197,14 +309,35 @@ impl Storage for DatabaseStorage {
         Ok(row.map(|r| RoomInfo {
             room_id: r.room_id,
             last_seq: r.last_seq as u64,
-            latest_snapshot: r.snap_covered.map(|ct| SnapshotInfo {
-                covered_through: ct as u64,
-                size_bytes: r.snap_size.unwrap_or(0) as u64,
-            }),
+            latest_snapshot: r
+                .snap_covered
+                .zip(r.snap_created_at)
+                .map(|(ct, created_at)| SnapshotInfo {
+                    covered_through: ct as u64,
+                    size_bytes: r.snap_size.unwrap_or(0) as u64,
+                    created_at,
+                }),
+            fork: r
+                .parent_room_id
+                .zip(r.forked_at_seq)
+                .map(|(parent, seq)| ForkPoint {
+                    parent,
+                    seq: seq as u64,
+                }),
+            title: r.title,
+            owner: r.owner,
+            description: r.description,
+            created_at: r.created_at,
+            updated_at: r.updated_at,
         }))
     }
 
-    async fn append_update(&self, room_id: &str, update: &[u8]) -> Result<LogSeq, Error> {
+    async fn append_update(
+        &self,
+        room_id: &str,
+        update: &[u8],
+        author: Option<&str>,
+    ) -> Result<LogSeq, Error> {
         let mut tx: Transaction<'_, Postgres> =
             self.db.pool().begin().await.map_err(Error::from)?;
 
@@ -213,11 +346,12 @@ impl Storage for DatabaseStorage {
 
         sqlx::query!(
             r#"
-            INSERT INTO room_updates (room_id, seq, bytes)
-                VALUES ($1, $2, $3)"#,
+            INSERT INTO room_updates (room_id, seq, bytes, author)
+                VALUES ($1, $2, $3, $4)"#,
             room_id,
             first,
-            update
+            update,
+            author
         )
         .execute(&mut *tx)
         .await
@@ -230,7 +364,7 @@ impl Storage for DatabaseStorage {
     async fn append_updates(
         &self,
         room_id: &str,
-        updates: &[Vec<u8>],
+        updates: &[NewUpdate],
     ) -> Result<(LogSeq, LogSeq), Error> {
         if updates.is_empty() {
             return Err(Error::InvalidArgument(
@@ -242,6 +376,11 @@ impl Storage for DatabaseStorage {
             .try_into()
             .map_err(|_| Error::InvalidArgument("too many updates".to_string()))?;
 
+        let (bytes, authors): (Vec<&[u8]>, Vec<Option<&str>>) = updates
+            .iter()
+            .map(|u| (u.bytes.as_slice(), u.author.as_deref()))
+            .unzip();
+
         let mut tx: Transaction<'_, Postgres> =
             self.db.pool().begin().await.map_err(Error::from)?;
 
@@ -250,21 +389,23 @@ impl Storage for DatabaseStorage {
         // Insert all updates; preserve caller order.
         sqlx::query!(
             r#"
-            INSERT INTO room_updates (room_id, seq, bytes)
+            INSERT INTO room_updates (room_id, seq, bytes, author)
             SELECT
                 $1 AS room_id,
                 ($2 + gs.i) AS seq,
-                u.bytes AS bytes
+                u.bytes AS bytes,
+                u.author AS author
             FROM
                 generate_series(0::bigint, $3 - 1::bigint) AS gs (i)
-                JOIN unnest($4::bytea[])
-                WITH ORDINALITY AS u (bytes, ord) ON u.ord = gs.i + 1
+                JOIN unnest($4::bytea[], $5::text[])
+                WITH ORDINALITY AS u (bytes, author, ord) ON u.ord = gs.i + 1
             ORDER BY
                 gs.i"#,
             room_id,
             first,
             n,
-            updates as &[Vec<u8>]
+            &bytes as &[&[u8]],
+            &authors as &[Option<&str>]
         )
         .execute(&mut *tx)
         .await
@@ -286,7 +427,9 @@ impl Storage for DatabaseStorage {
             r#"
                 SELECT
                     seq,
-                    bytes
+                    bytes,
+                    author,
+                    created_at
                 FROM
                     room_updates
                 WHERE
@@ -304,18 +447,83 @@ impl Storage for DatabaseStorage {
         .await
         .map_err(Error::from)?;
 
+        // An empty log is only valid for a room that exists.
+        if rows.is_empty() && !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
         Ok(rows
             .into_iter()
             .map(|r| UpdateEntry {
                 seq: r.seq as u64,
                 bytes: r.bytes,
+                author: r.author,
+                created_at: r.created_at,
             })
             .collect())
     }
 
+    async fn last_seq_at(&self, room_id: &str, at: DateTime<Utc>) -> Result<LogSeq, Error> {
+        if !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
+        let row = sqlx::query!(
+            r#"
+            SELECT
+                MAX(seq) AS "seq?"
+            FROM room_updates
+            WHERE
+                room_id = $1
+                AND created_at <= $2"#,
+            room_id,
+            at
+        )
+        .fetch_one(self.db.pool())
+        .await
+        .map_err(Error::from)?;
+
+        Ok(row.seq.unwrap_or(0) as LogSeq)
+    }
+
+    async fn prune_updates_through(
+        &self,
+        room_id: &str,
+        through: LogSeq,
+        opts: PruneUpdatesOptions,
+    ) -> Result<u64, Error> {
+        if !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
+        // Never prune past the newest snapshot, otherwise `load_doc` could not rebuild the room.
+        let res = sqlx::query!(
+            r#"
+            DELETE FROM room_updates
+            WHERE room_id = $1
+                AND seq <= LEAST($2, (
+                        SELECT
+                            COALESCE(MAX(covered_through), 0)
+                        FROM room_snapshots
+                        WHERE
+                            room_id = $1))
+                AND ($3::timestamptz IS NULL
+                    OR created_at < $3)"#,
+            room_id,
+            through as i64,
+            opts.created_before
+        )
+        .execute(self.db.pool())
+        .await
+        .map_err(Error::from)?;
+
+        Ok(res.rows_affected())
+    }
+
     async fn store_snapshot(&self, room_id: &str, snapshot: Snapshot) -> Result<(), Error> {
         // Upsert snapshot by (room_id, covered_through).
         // You may want to enforce snapshot.covered_through <= rooms.last_seq in a later iteration.
+        // A missing room violates the foreign key and is reported as `NotFound`.
         sqlx::query!(
             r#"
             INSERT INTO room_snapshots (room_id, covered_through, bytes)
@@ -329,7 +537,7 @@ impl Storage for DatabaseStorage {
         )
         .execute(self.db.pool())
         .await
-        .map_err(Error::from)?;
+        .map_err(missing_room_on_fk)?;
 
         Ok(())
     }
@@ -356,6 +564,10 @@ impl Storage for DatabaseStorage {
         .await
         .map_err(Error::from)?;
 
+        if row.is_none() && !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
         Ok(row.map(|r| Snapshot {
             covered_through: r.covered_through as u64,
             bytes: r.bytes,
@@ -388,6 +600,10 @@ impl Storage for DatabaseStorage {
         .await
         .map_err(Error::from)?;
 
+        if row.is_none() && !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
         Ok(row.map(|r| Snapshot {
             covered_through: r.covered_through as u64,
             bytes: r.bytes,
@@ -399,7 +615,8 @@ impl Storage for DatabaseStorage {
             r#"
             SELECT
                 covered_through,
-                octet_length(bytes)::bigint AS "size_bytes!"
+                octet_length(bytes)::bigint AS "size_bytes!",
+                created_at
             FROM
                 room_snapshots
             WHERE
@@ -412,12 +629,516 @@ impl Storage for DatabaseStorage {
         .await
         .map_err(Error::from)?;
 
+        if rows.is_empty() && !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
         Ok(rows
             .into_iter()
             .map(|r| SnapshotInfo {
                 covered_through: r.covered_through as u64,
                 size_bytes: r.size_bytes as u64,
+                created_at: r.created_at,
+            })
+            .collect())
+    }
+
+    async fn delete_snapshots(
+        &self,
+        room_id: &str,
+        covered_through: &[LogSeq],
+    ) -> Result<u64, Error> {
+        if !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
+        let covered_through: Vec<i64> = covered_through.iter().map(|&c| c as i64).collect();
+
+        let res = sqlx::query!(
+            r#"
+            DELETE FROM room_snapshots
+            WHERE room_id = $1
+                AND covered_through = ANY ($2::bigint[])"#,
+            room_id,
+            &covered_through
+        )
+        .execute(self.db.pool())
+        .await
+        .map_err(Error::from)?;
+
+        Ok(res.rows_affected())
+    }
+
+    async fn record_client(
+        &self,
+        room_id: &str,
+        client_id: u64,
+        user_id: &str,
+    ) -> Result<bool, Error> {
+        // A missing room violates the foreign key and is reported as `NotFound`. The no-op update
+        // makes the conflicting row come back with the user recorded first.
+        let recorded = sqlx::query_scalar!(
+            r#"
+            INSERT INTO room_clients (room_id, client_id, user_id)
+                VALUES ($1, $2, $3)
+            ON CONFLICT (room_id, client_id)
+                DO UPDATE SET user_id = room_clients.user_id
+            RETURNING user_id"#,
+            room_id,
+            client_id as i64,
+            user_id
+        )
+        .fetch_one(self.db.pool())
+        .await
+        .map_err(missing_room_on_fk)?;
+
+        Ok(recorded == user_id)
+    }
+
+    async fn list_clients(&self, room_id: &str) -> Result<Vec<ClientInfo>, Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                client_id,
+                user_id,
+                first_seen
+            FROM
+                room_clients
+            WHERE
+                room_id = $1
+            ORDER BY
+                client_id ASC"#,
+            room_id
+        )
+        .fetch_all(self.db.pool())
+        .await
+        .map_err(Error::from)?;
+
+        if rows.is_empty() && !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
+        Ok(rows
+            .into_iter()
+            .map(|r| ClientInfo {
+                client_id: r.client_id as u64,
+                user_id: r.user_id,
+                first_seen: r.first_seen,
             })
             .collect())
     }
+
+    async fn create_version(&self, room_id: &str, version: NewVersion) -> Result<Version, Error> {
+        // A missing room violates the foreign key and is reported as `NotFound`.
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO room_versions (room_id, label, seq, author, note)
+                VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (room_id, label)
+                DO NOTHING
+            RETURNING
+                created_at"#,
+            room_id,
+            version.label,
+            version.seq as i64,
+            version.author,
+            version.note
+        )
+        .fetch_optional(self.db.pool())
+        .await
+        .map_err(missing_room_on_fk)?
+        .ok_or(Error::AlreadyExists)?;
+
+        Ok(Version {
+            label: version.label,
+            seq: version.seq,
+            author: version.author,
+            note: version.note,
+            created_at: row.created_at,
+        })
+    }
+
+    async fn list_versions(&self, room_id: &str) -> Result<Vec<Version>, Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                label,
+                seq,
+                author,
+                note,
+                created_at
+            FROM
+                room_versions
+            WHERE
+                room_id = $1
+            ORDER BY
+                seq ASC,
+                created_at ASC"#,
+            room_id
+        )
+        .fetch_all(self.db.pool())
+        .await
+        .map_err(Error::from)?;
+
+        if rows.is_empty() && !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
+        Ok(rows
+            .into_iter()
+            .map(|r| Version {
+                label: r.label,
+                seq: r.seq as u64,
+                author: r.author,
+                note: r.note,
+                created_at: r.created_at,
+            })
+            .collect())
+    }
+
+    async fn get_version(&self, room_id: &str, label: &str) -> Result<Option<Version>, Error> {
+        let row = sqlx::query!(
+            r#"
+            SELECT
+                label,
+                seq,
+                author,
+                note,
+                created_at
+            FROM
+                room_versions
+            WHERE
+                room_id = $1
+                AND label = $2"#,
+            room_id,
+            label
+        )
+        .fetch_optional(self.db.pool())
+        .await
+        .map_err(Error::from)?;
+
+        if row.is_none() && !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
+        Ok(row.map(|r| Version {
+            label: r.label,
+            seq: r.seq as u64,
+            author: r.author,
+            note: r.note,
+            created_at: r.created_at,
+        }))
+    }
+
+    async fn delete_version(&self, room_id: &str, label: &str) -> Result<(), Error> {
+        let res = sqlx::query!(
+            r#"
+            DELETE FROM room_versions
+            WHERE room_id = $1
+                AND label = $2"#,
+            room_id,
+            label
+        )
+        .execute(self.db.pool())
+        .await
+        .map_err(Error::from)?;
+
+        if res.rows_affected() == 0 {
+            return Err(Error::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn set_member(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        role: Role,
+        share_id: Option<&str>,
+    ) -> Result<Member, Error> {
+        // A missing room or share violates a foreign key and is reported as `NotFound`.
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO room_members (room_id, user_id, role, share_id)
+                VALUES ($1, $2, $3, $4)
+            ON CONFLICT (room_id, user_id)
+                DO UPDATE SET
+                    role = excluded.role,
+                    share_id = excluded.share_id,
+                    granted_at = now()
+            RETURNING
+                granted_at"#,
+            room_id,
+            user_id,
+            role.as_str(),
+            share_id
+        )
+        .fetch_one(self.db.pool())
+        .await
+        .map_err(missing_room_on_fk)?;
+
+        Ok(Member {
+            user_id: user_id.to_string(),
+            role,
+            share_id: share_id.map(str::to_string),
+            granted_at: row.granted_at,
+        })
+    }
+
+    async fn remove_member(&self, room_id: &str, user_id: &str) -> Result<(), Error> {
+        let res = sqlx::query!(
+            r#"
+            DELETE FROM room_members
+            WHERE room_id = $1
+                AND user_id = $2"#,
+            room_id,
+            user_id
+        )
+        .execute(self.db.pool())
+        .await
+        .map_err(Error::from)?;
+
+        if res.rows_affected() == 0 {
+            return Err(Error::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn get_member(&self, room_id: &str, user_id: &str) -> Result<Option<Member>, Error> {
+        let row = sqlx::query!(
+            r#"
+            SELECT
+                user_id,
+                role,
+                share_id,
+                granted_at
+            FROM
+                room_members
+            WHERE
+                room_id = $1
+                AND user_id = $2"#,
+            room_id,
+            user_id
+        )
+        .fetch_optional(self.db.pool())
+        .await
+        .map_err(Error::from)?;
+
+        if row.is_none() && !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
+        row.map(|r| {
+            Ok(Member {
+                user_id: r.user_id,
+                role: stored_role(&r.role)?,
+                share_id: r.share_id,
+                granted_at: r.granted_at,
+            })
+        })
+        .transpose()
+    }
+
+    async fn list_members(&self, room_id: &str) -> Result<Vec<Member>, Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                user_id,
+                role,
+                share_id,
+                granted_at
+            FROM
+                room_members
+            WHERE
+                room_id = $1
+            ORDER BY
+                user_id ASC"#,
+            room_id
+        )
+        .fetch_all(self.db.pool())
+        .await
+        .map_err(Error::from)?;
+
+        if rows.is_empty() && !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
+        rows.into_iter()
+            .map(|r| {
+                Ok(Member {
+                    user_id: r.user_id,
+                    role: stored_role(&r.role)?,
+                    share_id: r.share_id,
+                    granted_at: r.granted_at,
+                })
+            })
+            .collect()
+    }
+
+    async fn list_memberships(&self, user_id: &str) -> Result<Vec<(String, Role)>, Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                room_id,
+                role
+            FROM
+                room_members
+            WHERE
+                user_id = $1"#,
+            user_id
+        )
+        .fetch_all(self.db.pool())
+        .await
+        .map_err(Error::from)?;
+
+        rows.into_iter()
+            .map(|r| Ok((r.room_id, stored_role(&r.role)?)))
+            .collect()
+    }
+
+    async fn create_share(&self, room_id: &str, share: NewShare) -> Result<Share, Error> {
+        // A missing room violates the foreign key and is reported as `NotFound`.
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO room_shares (share_id, room_id, token, role, created_by, expires_at,
+                max_uses)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT
+                DO NOTHING
+            RETURNING
+                created_at"#,
+            share.share_id,
+            room_id,
+            share.token,
+            share.role.as_str(),
+            share.created_by,
+            share.expires_at,
+            share.max_uses.map(i64::from)
+        )
+        .fetch_optional(self.db.pool())
+        .await
+        .map_err(missing_room_on_fk)?
+        .ok_or(Error::AlreadyExists)?;
+
+        Ok(Share {
+            share_id: share.share_id,
+            room_id: room_id.to_string(),
+            token: share.token,
+            role: share.role,
+            created_by: share.created_by,
+            expires_at: share.expires_at,
+            max_uses: share.max_uses,
+            uses: 0,
+            created_at: row.created_at,
+        })
+    }
+
+    async fn list_shares(&self, room_id: &str) -> Result<Vec<Share>, Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                share_id,
+                room_id,
+                token,
+                role,
+                created_by,
+                expires_at,
+                max_uses,
+                uses,
+                created_at
+            FROM
+                room_shares
+            WHERE
+                room_id = $1
+            ORDER BY
+                created_at ASC"#,
+            room_id
+        )
+        .fetch_all(self.db.pool())
+        .await
+        .map_err(Error::from)?;
+
+        if rows.is_empty() && !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
+        rows.into_iter()
+            .map(|r| {
+                Ok(Share {
+                    share_id: r.share_id,
+                    room_id: r.room_id,
+                    token: r.token,
+                    role: stored_role(&r.role)?,
+                    created_by: r.created_by,
+                    expires_at: r.expires_at,
+                    max_uses: r.max_uses.map(|n| n as u32),
+                    uses: r.uses as u32,
+                    created_at: r.created_at,
+                })
+            })
+            .collect()
+    }
+
+    async fn delete_share(&self, room_id: &str, share_id: &str) -> Result<(), Error> {
+        let res = sqlx::query!(
+            r#"
+            DELETE FROM room_shares
+            WHERE room_id = $1
+                AND share_id = $2"#,
+            room_id,
+            share_id
+        )
+        .execute(self.db.pool())
+        .await
+        .map_err(Error::from)?;
+
+        if res.rows_affected() == 0 {
+            return Err(Error::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn use_share(&self, token: &str, now: DateTime<Utc>) -> Result<Share, Error> {
+        let r = sqlx::query!(
+            r#"
+            UPDATE
+                room_shares
+            SET
+                uses = uses + 1
+            WHERE
+                token = $1
+                AND (expires_at IS NULL
+                    OR expires_at > $2)
+                AND (max_uses IS NULL
+                    OR uses < max_uses)
+            RETURNING
+                share_id,
+                room_id,
+                token,
+                role,
+                created_by,
+                expires_at,
+                max_uses,
+                uses,
+                created_at"#,
+            token,
+            now
+        )
+        .fetch_optional(self.db.pool())
+        .await
+        .map_err(Error::from)?
+        .ok_or(Error::NotFound)?;
+
+        Ok(Share {
+            share_id: r.share_id,
+            room_id: r.room_id,
+            token: r.token,
+            role: stored_role(&r.role)?,
+            created_by: r.created_by,
+            expires_at: r.expires_at,
+            max_uses: r.max_uses.map(|n| n as u32),
+            uses: r.uses as u32,
+            created_at: r.created_at,
+        })
+    }
 }
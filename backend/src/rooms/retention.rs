@@ -0,0 +1,188 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+
+use crate::config::{SnapshotRetention, UpdateRetention};
+use crate::rooms::error::Error;
+use crate::rooms::storage::{LogSeq, PruneUpdatesOptions, SnapshotInfo, Storage};
+
+/// Remove updates covered by the snapshot at `covered_through` that fall outside `policy`.
+///
+/// Returns the number of removed updates.
+pub async fn prune_updates(
+    storage: &dyn Storage,
+    room_id: &str,
+    covered_through: LogSeq,
+    policy: &UpdateRetention,
+) -> Result<u64, Error> {
+    let through = covered_through.saturating_sub(policy.keep_last);
+    if through == 0 {
+        return Ok(0);
+    }
+
+    let created_before = Utc::now() - TimeDelta::days(policy.keep_days.into());
+
+    storage
+        .prune_updates_through(
+            room_id,
+            through,
+            PruneUpdatesOptions {
+                created_before: Some(created_before),
+            },
+        )
+        .await
+}
+
+/// Remove the snapshots of the room that fall outside `policy`.
+///
+/// Snapshots pinned by a named version are always kept. Returns the snapshots that were removed.
+pub async fn gc_snapshots(
+    storage: &dyn Storage,
+    room_id: &str,
+    policy: &SnapshotRetention,
+) -> Result<Vec<SnapshotInfo>, Error> {
+    let snapshots = storage.list_snapshots(room_id).await?;
+    let pinned: HashSet<LogSeq> = storage
+        .list_versions(room_id)
+        .await?
+        .into_iter()
+        .map(|v| v.seq)
+        .collect();
+
+    let mut expired = expired_snapshots(&snapshots, Utc::now(), policy);
+    expired.retain(|s| !pinned.contains(&s.covered_through));
+    if expired.is_empty() {
+        return Ok(expired);
+    }
+
+    let covered_through: Vec<LogSeq> = expired.iter().map(|s| s.covered_through).collect();
+    storage.delete_snapshots(room_id, &covered_through).await?;
+
+    Ok(expired)
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum Bucket {
+    Hour(i64),
+    Day(NaiveDate),
+}
+
+/// Select the snapshots that `policy` no longer keeps.
+///
+/// `snapshots` must be ordered by `covered_through` ascending. Within each hour or day bucket
+/// the newest snapshot is kept, and the newest snapshot overall is never selected.
+fn expired_snapshots(
+    snapshots: &[SnapshotInfo],
+    now: DateTime<Utc>,
+    policy: &SnapshotRetention,
+) -> Vec<SnapshotInfo> {
+    let keep_all_after = now - TimeDelta::hours(policy.keep_all_hours.into());
+    let keep_hourly_after = now - TimeDelta::days(policy.keep_hourly_days.into());
+
+    let mut seen = HashSet::new();
+    let mut expired = Vec::new();
+
+    for (i, s) in snapshots.iter().rev().enumerate() {
+        let keep = if s.created_at >= keep_all_after {
+            true
+        } else if s.created_at >= keep_hourly_after {
+            seen.insert(Bucket::Hour(s.created_at.timestamp().div_euclid(3600)))
+        } else {
+            seen.insert(Bucket::Day(s.created_at.date_naive()))
+        };
+
+        if !keep && i != 0 {
+            expired.push(s.clone());
+        }
+    }
+
+    expired.reverse();
+    expired
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    const POLICY: SnapshotRetention = SnapshotRetention {
+        keep_all_hours: 2,
+        keep_hourly_days: 2,
+    };
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 3, 10, 12, 0, 0).unwrap()
+    }
+
+    /// Snapshots taken `ago` before [`now`], oldest first, numbered from 1.
+    fn snapshots(ago: &[TimeDelta]) -> Vec<SnapshotInfo> {
+        ago.iter()
+            .enumerate()
+            .map(|(i, ago)| SnapshotInfo {
+                covered_through: i as LogSeq + 1,
+                size_bytes: 1,
+                created_at: now() - *ago,
+            })
+            .collect()
+    }
+
+    fn expired(snapshots: &[SnapshotInfo]) -> Vec<LogSeq> {
+        expired_snapshots(snapshots, now(), &POLICY)
+            .into_iter()
+            .map(|s| s.covered_through)
+            .collect()
+    }
+
+    fn minutes(m: i64) -> TimeDelta {
+        TimeDelta::minutes(m)
+    }
+
+    #[test]
+    fn keeps_everything_in_the_keep_all_window() {
+        let s = snapshots(&[minutes(119), minutes(60), minutes(30), minutes(1)]);
+        assert!(expired(&s).is_empty());
+    }
+
+    #[test]
+    fn keeps_the_newest_snapshot_per_hour() {
+        // 12:00 is now, so these fall in the 07:00, 07:00, 08:00, 08:00 and 08:00 hours.
+        let s = snapshots(&[
+            minutes(5 * 60 - 10),
+            minutes(5 * 60 - 50),
+            minutes(4 * 60 - 5),
+            minutes(4 * 60 - 30),
+            minutes(4 * 60 - 55),
+            minutes(10),
+        ]);
+        assert_eq!(expired(&s), [1, 3, 4]);
+    }
+
+    #[test]
+    fn keeps_the_newest_snapshot_per_day() {
+        let day = TimeDelta::days(1);
+        // On March 5th, 5th, 6th and 7th.
+        let s = snapshots(&[
+            day * 5 + minutes(60),
+            day * 5,
+            day * 4,
+            day * 3,
+            minutes(10),
+        ]);
+        assert_eq!(expired(&s), [1]);
+    }
+
+    #[test]
+    fn never_expires_the_newest_snapshot() {
+        let day = TimeDelta::days(1);
+        let s = snapshots(&[day * 30, day * 30 - minutes(1)]);
+        assert_eq!(expired(&s), [1]);
+
+        let policy = SnapshotRetention {
+            keep_all_hours: 0,
+            keep_hourly_days: 0,
+        };
+        let s = snapshots(&[day * 30]);
+        assert!(expired_snapshots(&s, now(), &policy).is_empty());
+    }
+}
@@ -0,0 +1,494 @@
+use axum::Json;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::Redirect;
+use axum_extra::extract::CookieJar;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::auth::{Session, session_cookie};
+use crate::rooms::Error;
+use crate::rooms::blame::BlameLine;
+use crate::rooms::diff::ScriptDiff;
+use crate::rooms::history::{self, At};
+use crate::rooms::merge::Merge;
+use crate::rooms::storage::{LogSeq, Member, Role, RoomInfo, RoomMetadataUpdate, Share, Version};
+use crate::{auth::AuthSession, state::AppState};
+
+#[derive(Deserialize)]
+pub struct NewRoomBody {
+    pub title: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Changes to a room's metadata, fields left out are kept.
+#[derive(Deserialize)]
+pub struct RoomUpdateBody {
+    pub title: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Every room the caller may view, ordered by id.
+pub async fn list_rooms(
+    AuthSession(session): AuthSession,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<RoomInfo>>, Error> {
+    Ok(Json(state.rooms.list_rooms(&session.user_id).await?))
+}
+
+/// Create a room owned by the caller under a generated id.
+pub async fn create_room(
+    AuthSession(session): AuthSession,
+    State(state): State<AppState>,
+    Json(body): Json<NewRoomBody>,
+) -> Result<(StatusCode, Json<RoomInfo>), Error> {
+    if session.guest {
+        return Err(Error::Forbidden);
+    }
+    let info = state
+        .rooms
+        .create_room(&session.user_id, body.title, body.description)
+        .await?;
+    Ok((StatusCode::CREATED, Json(info)))
+}
+
+pub async fn get_room(
+    AuthSession(session): AuthSession,
+    State(state): State<AppState>,
+    Path(room_id): Path<String>,
+) -> Result<Json<RoomInfo>, Error> {
+    state
+        .rooms
+        .authorize(&room_id, &session.user_id, Role::Viewer)
+        .await?;
+    Ok(Json(state.rooms.room_info(&room_id).await?))
+}
+
+pub async fn update_room(
+    AuthSession(session): AuthSession,
+    State(state): State<AppState>,
+    Path(room_id): Path<String>,
+    Json(body): Json<RoomUpdateBody>,
+) -> Result<Json<RoomInfo>, Error> {
+    state
+        .rooms
+        .authorize(&room_id, &session.user_id, Role::Editor)
+        .await?;
+    let update = RoomMetadataUpdate {
+        title: body.title,
+        description: body.description,
+        ..Default::default()
+    };
+    Ok(Json(state.rooms.update_room(&room_id, update).await?))
+}
+
+/// Delete the room, closing any connections to it.
+pub async fn delete_room(
+    AuthSession(session): AuthSession,
+    State(state): State<AppState>,
+    Path(room_id): Path<String>,
+) -> Result<StatusCode, Error> {
+    state
+        .rooms
+        .authorize(&room_id, &session.user_id, Role::Owner)
+        .await?;
+    state.rooms.delete_room(&room_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Selects a point in a room's history, either by `seq` or by time `at` (RFC 3339).
+#[derive(Deserialize)]
+pub struct AtQuery {
+    pub seq: Option<LogSeq>,
+    pub at: Option<DateTime<Utc>>,
+}
+
+impl TryFrom<AtQuery> for At {
+    type Error = Error;
+
+    fn try_from(q: AtQuery) -> Result<Self, Error> {
+        match (q.seq, q.at) {
+            (Some(seq), None) => Ok(At::Seq(seq)),
+            (None, Some(time)) => Ok(At::Time(time)),
+            _ => Err(Error::InvalidArgument(
+                "exactly one of seq and at is required".to_string(),
+            )),
+        }
+    }
+}
+
+/// The Fountain text of the room at the requested point.
+pub async fn text_at(
+    AuthSession(session): AuthSession,
+    State(state): State<AppState>,
+    Path(room_id): Path<String>,
+    Query(query): Query<AtQuery>,
+) -> Result<String, Error> {
+    state
+        .rooms
+        .authorize(&room_id, &session.user_id, Role::Viewer)
+        .await?;
+    let doc = state.rooms.doc_at(&room_id, query.try_into()?).await?;
+    Ok(history::text(&doc))
+}
+
+/// Restore the room's text to the requested point, as a new edit.
+pub async fn restore(
+    AuthSession(session): AuthSession,
+    State(state): State<AppState>,
+    Path(room_id): Path<String>,
+    Query(query): Query<AtQuery>,
+) -> Result<StatusCode, Error> {
+    state
+        .rooms
+        .authorize(&room_id, &session.user_id, Role::Editor)
+        .await?;
+    state
+        .rooms
+        .restore(&room_id, query.try_into()?, &session.user_id)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Who inserted each line of the room's text and when.
+pub async fn blame(
+    AuthSession(session): AuthSession,
+    State(state): State<AppState>,
+    Path(room_id): Path<String>,
+) -> Result<Json<Vec<BlameLine>>, Error> {
+    state
+        .rooms
+        .authorize(&room_id, &session.user_id, Role::Viewer)
+        .await?;
+    Ok(Json(state.rooms.blame(&room_id).await?))
+}
+
+#[derive(Deserialize)]
+pub struct NewVersionBody {
+    pub label: String,
+    /// Defaults to the end of the log.
+    pub seq: Option<LogSeq>,
+    pub note: Option<String>,
+}
+
+/// The named versions of the room.
+pub async fn list_versions(
+    AuthSession(session): AuthSession,
+    State(state): State<AppState>,
+    Path(room_id): Path<String>,
+) -> Result<Json<Vec<Version>>, Error> {
+    state
+        .rooms
+        .authorize(&room_id, &session.user_id, Role::Viewer)
+        .await?;
+    Ok(Json(state.rooms.list_versions(&room_id).await?))
+}
+
+/// Name a point of the room's history.
+pub async fn create_version(
+    AuthSession(session): AuthSession,
+    State(state): State<AppState>,
+    Path(room_id): Path<String>,
+    Json(body): Json<NewVersionBody>,
+) -> Result<(StatusCode, Json<Version>), Error> {
+    state
+        .rooms
+        .authorize(&room_id, &session.user_id, Role::Editor)
+        .await?;
+    let version = state
+        .rooms
+        .create_version(&room_id, &body.label, body.seq, &session.user_id, body.note)
+        .await?;
+    Ok((StatusCode::CREATED, Json(version)))
+}
+
+pub async fn delete_version(
+    AuthSession(session): AuthSession,
+    State(state): State<AppState>,
+    Path((room_id, label)): Path<(String, String)>,
+) -> Result<StatusCode, Error> {
+    state
+        .rooms
+        .authorize(&room_id, &session.user_id, Role::Editor)
+        .await?;
+    state.rooms.delete_version(&room_id, &label).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The Fountain text of a named version.
+pub async fn version_text(
+    AuthSession(session): AuthSession,
+    State(state): State<AppState>,
+    Path((room_id, label)): Path<(String, String)>,
+) -> Result<String, Error> {
+    state
+        .rooms
+        .authorize(&room_id, &session.user_id, Role::Viewer)
+        .await?;
+    let (_, doc) = state.rooms.version_doc(&room_id, &label).await?;
+    Ok(history::text(&doc))
+}
+
+/// The two sides of a diff, each either a `seq` or the label of a named version. Without a
+/// `to` side the diff runs up to the end of the log.
+#[derive(Deserialize)]
+pub struct DiffQuery {
+    pub from_seq: Option<LogSeq>,
+    pub from_version: Option<String>,
+    pub to_seq: Option<LogSeq>,
+    pub to_version: Option<String>,
+}
+
+/// Compare two versions of the room's script by scene and dialogue, with a unified text diff.
+pub async fn diff(
+    AuthSession(session): AuthSession,
+    State(state): State<AppState>,
+    Path(room_id): Path<String>,
+    Query(query): Query<DiffQuery>,
+) -> Result<Json<ScriptDiff>, Error> {
+    state
+        .rooms
+        .authorize(&room_id, &session.user_id, Role::Viewer)
+        .await?;
+    let side = async |seq, version: Option<String>| match (seq, version) {
+        (Some(seq), None) => Ok(Some(At::Seq(seq))),
+        (None, Some(label)) => Ok(Some(At::Seq(
+            state.rooms.version(&room_id, &label).await?.seq,
+        ))),
+        (None, None) => Ok(None),
+        _ => Err(Error::InvalidArgument(
+            "a side is either a seq or a version".to_string(),
+        )),
+    };
+
+    let from = side(query.from_seq, query.from_version)
+        .await?
+        .ok_or_else(|| {
+            Error::InvalidArgument("from_seq or from_version is required".to_string())
+        })?;
+    let to = side(query.to_seq, query.to_version)
+        .await?
+        .unwrap_or(At::Time(Utc::now()));
+
+    Ok(Json(state.rooms.diff(&room_id, from, to).await?))
+}
+
+#[derive(Deserialize)]
+pub struct ForkBody {
+    /// Id of the new room.
+    pub room_id: String,
+    /// The point to fork at, by `seq` or by time `at`. Defaults to the end of the log.
+    pub seq: Option<LogSeq>,
+    pub at: Option<DateTime<Utc>>,
+}
+
+/// Create a new room from the room's document at the requested point.
+pub async fn fork(
+    AuthSession(session): AuthSession,
+    State(state): State<AppState>,
+    Path(room_id): Path<String>,
+    Json(body): Json<ForkBody>,
+) -> Result<(StatusCode, Json<RoomInfo>), Error> {
+    // Guests may only use the rooms shared with them.
+    if session.guest {
+        return Err(Error::Forbidden);
+    }
+    state
+        .rooms
+        .authorize(&room_id, &session.user_id, Role::Viewer)
+        .await?;
+    let at = match (body.seq, body.at) {
+        (None, None) => At::Time(Utc::now()),
+        (seq, at) => AtQuery { seq, at }.try_into()?,
+    };
+    let info = state
+        .rooms
+        .fork_room(&room_id, &body.room_id, at, &session.user_id)
+        .await?;
+    Ok((StatusCode::CREATED, Json(info)))
+}
+
+#[derive(Deserialize)]
+pub struct MergeBody {
+    /// The room whose changes are merged in.
+    pub source: String,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Merge the changes of a fork into the room, or preview the result with `dry_run`.
+pub async fn merge(
+    AuthSession(session): AuthSession,
+    State(state): State<AppState>,
+    Path(room_id): Path<String>,
+    Json(body): Json<MergeBody>,
+) -> Result<Json<Merge>, Error> {
+    // A preview changes nothing, so viewing both rooms is enough for it.
+    let needed = if body.dry_run {
+        Role::Viewer
+    } else {
+        Role::Editor
+    };
+    state
+        .rooms
+        .authorize(&room_id, &session.user_id, needed)
+        .await?;
+    state
+        .rooms
+        .authorize(&body.source, &session.user_id, Role::Viewer)
+        .await?;
+    let merge = state
+        .rooms
+        .merge(&room_id, &body.source, &session.user_id, body.dry_run)
+        .await?;
+    Ok(Json(merge))
+}
+
+/// The users granted a role in the room, besides its owner.
+pub async fn list_members(
+    AuthSession(session): AuthSession,
+    State(state): State<AppState>,
+    Path(room_id): Path<String>,
+) -> Result<Json<Vec<Member>>, Error> {
+    state
+        .rooms
+        .authorize(&room_id, &session.user_id, Role::Viewer)
+        .await?;
+    Ok(Json(state.rooms.list_members(&room_id).await?))
+}
+
+#[derive(Deserialize)]
+pub struct MemberBody {
+    pub role: Role,
+}
+
+/// Grant a user a role in the room, replacing the role they had.
+pub async fn set_member(
+    AuthSession(session): AuthSession,
+    State(state): State<AppState>,
+    Path((room_id, user_id)): Path<(String, String)>,
+    Json(body): Json<MemberBody>,
+) -> Result<Json<Member>, Error> {
+    state
+        .rooms
+        .authorize(&room_id, &session.user_id, Role::Owner)
+        .await?;
+    Ok(Json(
+        state
+            .rooms
+            .set_member(&room_id, &user_id, body.role)
+            .await?,
+    ))
+}
+
+/// Take away the role of a user in the room.
+pub async fn remove_member(
+    AuthSession(session): AuthSession,
+    State(state): State<AppState>,
+    Path((room_id, user_id)): Path<(String, String)>,
+) -> Result<StatusCode, Error> {
+    state
+        .rooms
+        .authorize(&room_id, &session.user_id, Role::Owner)
+        .await?;
+    state.rooms.remove_member(&room_id, &user_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+pub struct NewShareBody {
+    pub role: Role,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_uses: Option<u32>,
+}
+
+/// The share links of the room, with their tokens.
+pub async fn list_shares(
+    AuthSession(session): AuthSession,
+    State(state): State<AppState>,
+    Path(room_id): Path<String>,
+) -> Result<Json<Vec<Share>>, Error> {
+    state
+        .rooms
+        .authorize(&room_id, &session.user_id, Role::Owner)
+        .await?;
+    Ok(Json(state.rooms.list_shares(&room_id).await?))
+}
+
+/// Create a share link for the room, visited at `/share/{token}`.
+pub async fn create_share(
+    AuthSession(session): AuthSession,
+    State(state): State<AppState>,
+    Path(room_id): Path<String>,
+    Json(body): Json<NewShareBody>,
+) -> Result<(StatusCode, Json<Share>), Error> {
+    state
+        .rooms
+        .authorize(&room_id, &session.user_id, Role::Owner)
+        .await?;
+    let share = state
+        .rooms
+        .create_share(
+            &room_id,
+            body.role,
+            &session.user_id,
+            body.expires_at,
+            body.max_uses,
+        )
+        .await?;
+    Ok((StatusCode::CREATED, Json(share)))
+}
+
+/// Revoke a share link along with the roles granted through it.
+pub async fn delete_share(
+    AuthSession(session): AuthSession,
+    State(state): State<AppState>,
+    Path((room_id, share_id)): Path<(String, String)>,
+) -> Result<StatusCode, Error> {
+    state
+        .rooms
+        .authorize(&room_id, &session.user_id, Role::Owner)
+        .await?;
+    state.rooms.delete_share(&room_id, &share_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Grant the role of a share link to the visitor and send them on to the room. Visitors who are
+/// not logged in get a guest session.
+pub async fn redeem_share(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    jar: CookieJar,
+) -> Result<(CookieJar, Redirect), Error> {
+    let session = match jar.get("session") {
+        Some(cookie) => state.auth.get_session(cookie.value()).await,
+        None => None,
+    };
+    let (session, is_new) = match session {
+        Some(session) => (session, false),
+        None => (Session::guest(), true),
+    };
+
+    let share = state.rooms.redeem_share(&token, &session.user_id).await?;
+
+    // Only a guest who got something out of the link is kept.
+    let jar = if is_new {
+        jar.add(session_cookie(state.auth.start_session(session).await))
+    } else {
+        jar
+    };
+    let to = format!("/?room={}", query_escape(&share.room_id));
+    Ok((jar, Redirect::to(&to)))
+}
+
+/// Percent-encode everything but the unreserved characters of RFC 3986.
+fn query_escape(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(b).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
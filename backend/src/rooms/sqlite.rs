@@ -0,0 +1,1080 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::sqlite::SqliteRow;
+use sqlx::{Executor, Row, Sqlite, SqlitePool, Transaction};
+
+use crate::rooms::error::Error;
+use crate::rooms::repo::{missing_room_on_fk, stored_role};
+use crate::rooms::storage::{
+    ClientInfo, CreateRoomOptions, ForkPoint, LoadUpdatesOptions, LogSeq, Member, NewShare,
+    NewUpdate, NewVersion, PruneUpdatesOptions, Role, RoomInfo, RoomMetadataUpdate, Share,
+    Snapshot, SnapshotInfo, Storage, UpdateEntry, Version,
+};
+
+/// [`Storage`] backed by a SQLite database.
+///
+/// Uses the schema in `migrations/sqlite`. Queries are checked at runtime since the
+/// compile time checked macros only run against the Postgres schema.
+#[derive(Clone)]
+pub struct SqliteStorage {
+    pool: SqlitePool,
+}
+
+impl SqliteStorage {
+    pub async fn new(pool: SqlitePool) -> Self {
+        let storage = Self { pool };
+        // Without an owner, the demo room is claimed by the first user to open it.
+        storage
+            .create_room(
+                "demo-room-1",
+                CreateRoomOptions {
+                    ..Default::default()
+                },
+            )
+            .await
+            .expect("Initialization calls should work");
+
+        storage
+    }
+
+    /// Same contract as `DatabaseStorage::alloc_seq_range`.
+    async fn alloc_seq_range<'e, E>(ex: E, room_id: &str, n: i64) -> Result<(i64, i64), Error>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        // Should always allocate one or more updates
+        debug_assert!(n > 0);
+        let row = sqlx::query(
+            r#"
+            UPDATE
+                rooms
+            SET
+                last_seq = last_seq + ?2,
+                updated_at = ?3
+            WHERE
+                room_id = ?1
+            RETURNING (last_seq - ?2 + 1) AS first_seq,
+            last_seq"#,
+        )
+        .bind(room_id)
+        .bind(n)
+        .bind(Utc::now())
+        .fetch_optional(ex)
+        .await
+        .map_err(Error::from)?;
+
+        let Some(r) = row else {
+            return Err(Error::NotFound);
+        };
+
+        Ok((r.try_get("first_seq")?, r.try_get("last_seq")?))
+    }
+
+    fn version(r: &SqliteRow) -> Result<Version, Error> {
+        Ok(Version {
+            label: r.try_get("label")?,
+            seq: r.try_get::<i64, _>("seq")? as u64,
+            author: r.try_get("author")?,
+            note: r.try_get("note")?,
+            created_at: r.try_get("created_at")?,
+        })
+    }
+
+    fn member(r: &SqliteRow) -> Result<Member, Error> {
+        Ok(Member {
+            user_id: r.try_get("user_id")?,
+            role: stored_role(r.try_get("role")?)?,
+            share_id: r.try_get("share_id")?,
+            granted_at: r.try_get("granted_at")?,
+        })
+    }
+
+    fn share(r: &SqliteRow) -> Result<Share, Error> {
+        let max_uses: Option<i64> = r.try_get("max_uses")?;
+        Ok(Share {
+            share_id: r.try_get("share_id")?,
+            room_id: r.try_get("room_id")?,
+            token: r.try_get("token")?,
+            role: stored_role(r.try_get("role")?)?,
+            created_by: r.try_get("created_by")?,
+            expires_at: r.try_get("expires_at")?,
+            max_uses: max_uses.map(|n| n as u32),
+            uses: r.try_get::<i64, _>("uses")? as u32,
+            created_at: r.try_get("created_at")?,
+        })
+    }
+
+    fn room_info(r: &SqliteRow) -> Result<RoomInfo, Error> {
+        let snap_covered: Option<i64> = r.try_get("snap_covered")?;
+        let snap_size: Option<i64> = r.try_get("snap_size")?;
+        let snap_created_at: Option<DateTime<Utc>> = r.try_get("snap_created_at")?;
+        let parent: Option<String> = r.try_get("parent_room_id")?;
+        let forked_at: Option<i64> = r.try_get("forked_at_seq")?;
+
+        Ok(RoomInfo {
+            room_id: r.try_get("room_id")?,
+            last_seq: r.try_get::<i64, _>("last_seq")? as u64,
+            latest_snapshot: snap_covered.zip(snap_created_at).map(|(ct, created_at)| {
+                SnapshotInfo {
+                    covered_through: ct as u64,
+                    size_bytes: snap_size.unwrap_or(0) as u64,
+                    created_at,
+                }
+            }),
+            fork: parent.zip(forked_at).map(|(parent, seq)| ForkPoint {
+                parent,
+                seq: seq as u64,
+            }),
+            title: r.try_get("title")?,
+            owner: r.try_get("owner")?,
+            description: r.try_get("description")?,
+            created_at: r.try_get("created_at")?,
+            updated_at: r.try_get("updated_at")?,
+        })
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn room_exists(&self, room_id: &str) -> Result<bool, Error> {
+        let r = sqlx::query(
+            r#"SELECT
+                   EXISTS (
+                       SELECT
+                           1
+                       FROM
+                           rooms
+                       WHERE
+                           room_id = ?1) AS "exists""#,
+        )
+        .bind(room_id)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(Error::from)?;
+
+        Ok(r.try_get("exists")?)
+    }
+
+    async fn create_room(&self, room_id: &str, opts: CreateRoomOptions) -> Result<(), Error> {
+        let res = sqlx::query(
+            r#"
+            INSERT INTO rooms (room_id, last_seq, parent_room_id, forked_at_seq, title, owner,
+                description, created_at, updated_at)
+                VALUES (?1, 0, ?2, ?3, ?4, ?5, ?6, ?7, ?7)
+            ON CONFLICT (room_id)
+                DO NOTHING"#,
+        )
+        .bind(room_id)
+        .bind(opts.fork.as_ref().map(|f| f.parent.as_str()))
+        .bind(opts.fork.as_ref().map(|f| f.seq as i64))
+        .bind(&opts.title)
+        .bind(&opts.owner)
+        .bind(&opts.description)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await
+        .map_err(Error::from)?;
+
+        if opts.fail_if_exists && res.rows_affected() == 0 {
+            return Err(Error::AlreadyExists);
+        }
+        Ok(())
+    }
+
+    async fn update_room_metadata(
+        &self,
+        room_id: &str,
+        update: RoomMetadataUpdate,
+    ) -> Result<(), Error> {
+        let res = sqlx::query(
+            r#"
+            UPDATE
+                rooms
+            SET
+                title = coalesce(?2, title),
+                owner = coalesce(?3, owner),
+                description = coalesce(?4, description)
+            WHERE
+                room_id = ?1"#,
+        )
+        .bind(room_id)
+        .bind(update.title)
+        .bind(update.owner)
+        .bind(update.description)
+        .execute(&self.pool)
+        .await
+        .map_err(Error::from)?;
+
+        if res.rows_affected() == 0 {
+            return Err(Error::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn claim_room(&self, room_id: &str, owner: &str) -> Result<bool, Error> {
+        let res = sqlx::query(
+            r#"
+            UPDATE
+                rooms
+            SET
+                owner = ?2
+            WHERE
+                room_id = ?1
+                AND owner IS NULL"#,
+        )
+        .bind(room_id)
+        .bind(owner)
+        .execute(&self.pool)
+        .await
+        .map_err(Error::from)?;
+
+        if res.rows_affected() == 0 && !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+        Ok(res.rows_affected() > 0)
+    }
+
+    async fn delete_room(&self, room_id: &str) -> Result<(), Error> {
+        // Relies on FK ON DELETE CASCADE, which sqlx enables by default for SQLite.
+        sqlx::query(
+            r#"DELETE FROM rooms
+                        WHERE room_id = ?1"#,
+        )
+        .bind(room_id)
+        .execute(&self.pool)
+        .await
+        .map_err(Error::from)?;
+        Ok(())
+    }
+
+    async fn list_rooms(&self) -> Result<Vec<RoomInfo>, Error> {
+        let rows = sqlx::query(
+            r#"
+            SELECT
+                r.room_id,
+                r.last_seq,
+                r.parent_room_id,
+                r.forked_at_seq,
+                r.title,
+                r.owner,
+                r.description,
+                r.created_at,
+                r.updated_at,
+                s.covered_through AS snap_covered,
+                length(s.bytes) AS snap_size,
+                s.created_at AS snap_created_at
+            FROM
+                rooms r
+                LEFT JOIN room_snapshots s ON s.room_id = r.room_id
+                    AND s.covered_through = (
+                        SELECT
+                            MAX(covered_through)
+                        FROM
+                            room_snapshots
+                        WHERE
+                            room_id = r.room_id)
+            ORDER BY
+                r.room_id ASC"#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::from)?;
+
+        rows.iter().map(SqliteStorage::room_info).collect()
+    }
+
+    async fn get_room_info(&self, room_id: &str) -> Result<Option<RoomInfo>, Error> {
+        let row = sqlx::query(
+            r#"
+            SELECT
+                r.room_id,
+                r.last_seq,
+                r.parent_room_id,
+                r.forked_at_seq,
+                r.title,
+                r.owner,
+                r.description,
+                r.created_at,
+                r.updated_at,
+                s.covered_through AS snap_covered,
+                length(s.bytes) AS snap_size,
+                s.created_at AS snap_created_at
+            FROM
+                rooms r
+                LEFT JOIN room_snapshots s ON s.room_id = r.room_id
+                    AND s.covered_through = (
+                        SELECT
+                            MAX(covered_through)
+                        FROM
+                            room_snapshots
+                        WHERE
+                            room_id = r.room_id)
+            WHERE
+                r.room_id = ?1"#,
+        )
+        .bind(room_id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Error::from)?;
+
+        row.as_ref().map(SqliteStorage::room_info).transpose()
+    }
+
+    async fn append_update(
+        &self,
+        room_id: &str,
+        update: &[u8],
+        author: Option<&str>,
+    ) -> Result<LogSeq, Error> {
+        let mut tx: Transaction<'_, Sqlite> = self.pool.begin().await.map_err(Error::from)?;
+
+        let (first, last) = SqliteStorage::alloc_seq_range(&mut *tx, room_id, 1).await?;
+        debug_assert_eq!(first, last);
+
+        sqlx::query(
+            r#"
+            INSERT INTO room_updates (room_id, seq, bytes, author, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5)"#,
+        )
+        .bind(room_id)
+        .bind(first)
+        .bind(update)
+        .bind(author)
+        .bind(Utc::now())
+        .execute(&mut *tx)
+        .await
+        .map_err(Error::from)?;
+
+        tx.commit().await.map_err(Error::from)?;
+        Ok(first as u64)
+    }
+
+    async fn append_updates(
+        &self,
+        room_id: &str,
+        updates: &[NewUpdate],
+    ) -> Result<(LogSeq, LogSeq), Error> {
+        if updates.is_empty() {
+            return Err(Error::InvalidArgument(
+                "updates must be non-empty".to_string(),
+            ));
+        }
+        let n: i64 = updates
+            .len()
+            .try_into()
+            .map_err(|_| Error::InvalidArgument("too many updates".to_string()))?;
+
+        let mut tx: Transaction<'_, Sqlite> = self.pool.begin().await.map_err(Error::from)?;
+
+        let (first, last) = SqliteStorage::alloc_seq_range(&mut *tx, room_id, n).await?;
+
+        // Insert all updates; preserve caller order.
+        let created_at = Utc::now();
+        for (seq, update) in (first..=last).zip(updates) {
+            sqlx::query(
+                r#"
+                INSERT INTO room_updates (room_id, seq, bytes, author, created_at)
+                    VALUES (?1, ?2, ?3, ?4, ?5)"#,
+            )
+            .bind(room_id)
+            .bind(seq)
+            .bind(&update.bytes)
+            .bind(&update.author)
+            .bind(created_at)
+            .execute(&mut *tx)
+            .await
+            .map_err(Error::from)?;
+        }
+
+        tx.commit().await.map_err(Error::from)?;
+        Ok((first as u64, last as u64))
+    }
+
+    async fn load_updates(
+        &self,
+        room_id: &str,
+        opts: LoadUpdatesOptions,
+    ) -> Result<Vec<UpdateEntry>, Error> {
+        let from: i64 = opts.from.map_or(1, |v| v as i64);
+        let to: Option<i64> = opts.to.map(|v| v as i64);
+
+        let rows = sqlx::query(
+            r#"
+                SELECT
+                    seq,
+                    bytes,
+                    author,
+                    created_at
+                FROM
+                    room_updates
+                WHERE
+                    room_id = ?1
+                    AND seq >= ?2
+                    AND (?3 IS NULL
+                        OR seq <= ?3)
+                ORDER BY
+                    seq ASC"#,
+        )
+        .bind(room_id)
+        .bind(from)
+        .bind(to)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::from)?;
+
+        // An empty log is only valid for a room that exists.
+        if rows.is_empty() && !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
+        rows.into_iter()
+            .map(|r| {
+                Ok(UpdateEntry {
+                    seq: r.try_get::<i64, _>("seq")? as u64,
+                    bytes: r.try_get("bytes")?,
+                    author: r.try_get("author")?,
+                    created_at: r.try_get("created_at")?,
+                })
+            })
+            .collect()
+    }
+
+    async fn last_seq_at(&self, room_id: &str, at: DateTime<Utc>) -> Result<LogSeq, Error> {
+        if !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
+        let r = sqlx::query(
+            r#"
+            SELECT
+                MAX(seq) AS seq
+            FROM room_updates
+            WHERE
+                room_id = ?1
+                AND julianday(created_at) <= julianday(?2)"#,
+        )
+        .bind(room_id)
+        .bind(at)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(Error::from)?;
+
+        let seq: Option<i64> = r.try_get("seq")?;
+        Ok(seq.unwrap_or(0) as LogSeq)
+    }
+
+    async fn prune_updates_through(
+        &self,
+        room_id: &str,
+        through: LogSeq,
+        opts: PruneUpdatesOptions,
+    ) -> Result<u64, Error> {
+        if !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
+        // Never prune past the newest snapshot, otherwise `load_doc` could not rebuild the room.
+        let res = sqlx::query(
+            r#"
+            DELETE FROM room_updates
+            WHERE room_id = ?1
+                AND seq <= MIN(?2, (
+                        SELECT
+                            COALESCE(MAX(covered_through), 0)
+                        FROM room_snapshots
+                        WHERE
+                            room_id = ?1))
+                AND (?3 IS NULL
+                    OR julianday(created_at) < julianday(?3))"#,
+        )
+        .bind(room_id)
+        .bind(through as i64)
+        .bind(opts.created_before)
+        .execute(&self.pool)
+        .await
+        .map_err(Error::from)?;
+
+        Ok(res.rows_affected())
+    }
+
+    async fn store_snapshot(&self, room_id: &str, snapshot: Snapshot) -> Result<(), Error> {
+        // Upsert snapshot by (room_id, covered_through).
+        sqlx::query(
+            r#"
+            INSERT INTO room_snapshots (room_id, covered_through, bytes, created_at)
+                VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (room_id, covered_through)
+                DO UPDATE SET
+                    bytes = excluded.bytes"#,
+        )
+        .bind(room_id)
+        .bind(snapshot.covered_through as i64)
+        .bind(snapshot.bytes)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await
+        .map_err(missing_room_on_fk)?;
+
+        Ok(())
+    }
+
+    async fn load_snapshot_at(
+        &self,
+        room_id: &str,
+        covered_through: LogSeq,
+    ) -> Result<Option<Snapshot>, Error> {
+        let row = sqlx::query(
+            r#"
+            SELECT
+                covered_through,
+                bytes
+            FROM
+                room_snapshots
+            WHERE
+                room_id = ?1
+                AND covered_through = ?2"#,
+        )
+        .bind(room_id)
+        .bind(covered_through as i64)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Error::from)?;
+
+        if row.is_none() && !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
+        row.map(|r| {
+            Ok(Snapshot {
+                covered_through: r.try_get::<i64, _>("covered_through")? as u64,
+                bytes: r.try_get("bytes")?,
+            })
+        })
+        .transpose()
+    }
+
+    async fn load_snapshot_best(
+        &self,
+        room_id: &str,
+        max_covered_through: Option<LogSeq>,
+    ) -> Result<Option<Snapshot>, Error> {
+        let row = sqlx::query(
+            r#"
+                SELECT
+                    covered_through,
+                    bytes
+                FROM
+                    room_snapshots
+                WHERE
+                    room_id = ?1
+                    AND (?2 IS NULL
+                        OR covered_through <= ?2)
+                ORDER BY
+                    covered_through DESC
+                LIMIT 1"#,
+        )
+        .bind(room_id)
+        .bind(max_covered_through.map(|max| max as i64))
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Error::from)?;
+
+        if row.is_none() && !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
+        row.map(|r| {
+            Ok(Snapshot {
+                covered_through: r.try_get::<i64, _>("covered_through")? as u64,
+                bytes: r.try_get("bytes")?,
+            })
+        })
+        .transpose()
+    }
+
+    async fn list_snapshots(&self, room_id: &str) -> Result<Vec<SnapshotInfo>, Error> {
+        let rows = sqlx::query(
+            r#"
+            SELECT
+                covered_through,
+                length(bytes) AS size_bytes,
+                created_at
+            FROM
+                room_snapshots
+            WHERE
+                room_id = ?1
+            ORDER BY
+                covered_through ASC"#,
+        )
+        .bind(room_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::from)?;
+
+        if rows.is_empty() && !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
+        rows.into_iter()
+            .map(|r| {
+                Ok(SnapshotInfo {
+                    covered_through: r.try_get::<i64, _>("covered_through")? as u64,
+                    size_bytes: r.try_get::<i64, _>("size_bytes")? as u64,
+                    created_at: r.try_get("created_at")?,
+                })
+            })
+            .collect()
+    }
+
+    async fn delete_snapshots(
+        &self,
+        room_id: &str,
+        covered_through: &[LogSeq],
+    ) -> Result<u64, Error> {
+        if !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
+        let mut tx: Transaction<'_, Sqlite> = self.pool.begin().await.map_err(Error::from)?;
+
+        let mut deleted = 0;
+        for &ct in covered_through {
+            let res = sqlx::query(
+                r#"
+                DELETE FROM room_snapshots
+                WHERE room_id = ?1
+                    AND covered_through = ?2"#,
+            )
+            .bind(room_id)
+            .bind(ct as i64)
+            .execute(&mut *tx)
+            .await
+            .map_err(Error::from)?;
+
+            deleted += res.rows_affected();
+        }
+
+        tx.commit().await.map_err(Error::from)?;
+        Ok(deleted)
+    }
+
+    async fn record_client(
+        &self,
+        room_id: &str,
+        client_id: u64,
+        user_id: &str,
+    ) -> Result<bool, Error> {
+        // The no-op update makes the conflicting row come back with the user recorded first.
+        let (recorded,): (String,) = sqlx::query_as(
+            r#"
+            INSERT INTO room_clients (room_id, client_id, user_id, first_seen)
+                VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (room_id, client_id)
+                DO UPDATE SET user_id = room_clients.user_id
+            RETURNING user_id"#,
+        )
+        .bind(room_id)
+        .bind(client_id as i64)
+        .bind(user_id)
+        .bind(Utc::now())
+        .fetch_one(&self.pool)
+        .await
+        .map_err(missing_room_on_fk)?;
+
+        Ok(recorded == user_id)
+    }
+
+    async fn list_clients(&self, room_id: &str) -> Result<Vec<ClientInfo>, Error> {
+        let rows = sqlx::query(
+            r#"
+            SELECT
+                client_id,
+                user_id,
+                first_seen
+            FROM
+                room_clients
+            WHERE
+                room_id = ?1
+            ORDER BY
+                client_id ASC"#,
+        )
+        .bind(room_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::from)?;
+
+        if rows.is_empty() && !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
+        rows.into_iter()
+            .map(|r| {
+                Ok(ClientInfo {
+                    client_id: r.try_get::<i64, _>("client_id")? as u64,
+                    user_id: r.try_get("user_id")?,
+                    first_seen: r.try_get("first_seen")?,
+                })
+            })
+            .collect()
+    }
+
+    async fn create_version(&self, room_id: &str, version: NewVersion) -> Result<Version, Error> {
+        let created_at = Utc::now();
+        let res = sqlx::query(
+            r#"
+            INSERT INTO room_versions (room_id, label, seq, author, note, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            ON CONFLICT (room_id, label)
+                DO NOTHING"#,
+        )
+        .bind(room_id)
+        .bind(&version.label)
+        .bind(version.seq as i64)
+        .bind(&version.author)
+        .bind(&version.note)
+        .bind(created_at)
+        .execute(&self.pool)
+        .await
+        .map_err(missing_room_on_fk)?;
+
+        if res.rows_affected() == 0 {
+            return Err(Error::AlreadyExists);
+        }
+        Ok(Version {
+            label: version.label,
+            seq: version.seq,
+            author: version.author,
+            note: version.note,
+            created_at,
+        })
+    }
+
+    async fn list_versions(&self, room_id: &str) -> Result<Vec<Version>, Error> {
+        let rows = sqlx::query(
+            r#"
+            SELECT
+                label,
+                seq,
+                author,
+                note,
+                created_at
+            FROM
+                room_versions
+            WHERE
+                room_id = ?1
+            ORDER BY
+                seq ASC,
+                julianday(created_at) ASC"#,
+        )
+        .bind(room_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::from)?;
+
+        if rows.is_empty() && !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
+        rows.iter().map(SqliteStorage::version).collect()
+    }
+
+    async fn get_version(&self, room_id: &str, label: &str) -> Result<Option<Version>, Error> {
+        let row = sqlx::query(
+            r#"
+            SELECT
+                label,
+                seq,
+                author,
+                note,
+                created_at
+            FROM
+                room_versions
+            WHERE
+                room_id = ?1
+                AND label = ?2"#,
+        )
+        .bind(room_id)
+        .bind(label)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Error::from)?;
+
+        if row.is_none() && !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
+        row.as_ref().map(SqliteStorage::version).transpose()
+    }
+
+    async fn delete_version(&self, room_id: &str, label: &str) -> Result<(), Error> {
+        let res = sqlx::query(
+            r#"
+            DELETE FROM room_versions
+            WHERE room_id = ?1
+                AND label = ?2"#,
+        )
+        .bind(room_id)
+        .bind(label)
+        .execute(&self.pool)
+        .await
+        .map_err(Error::from)?;
+
+        if res.rows_affected() == 0 {
+            return Err(Error::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn set_member(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        role: Role,
+        share_id: Option<&str>,
+    ) -> Result<Member, Error> {
+        let granted_at = Utc::now();
+        // A missing share violates the foreign key just like a missing room.
+        sqlx::query(
+            r#"
+            INSERT INTO room_members (room_id, user_id, role, share_id, granted_at)
+                VALUES (?1, ?2, ?3, ?4, ?5)
+            ON CONFLICT (room_id, user_id)
+                DO UPDATE SET
+                    role = excluded.role,
+                    share_id = excluded.share_id,
+                    granted_at = excluded.granted_at"#,
+        )
+        .bind(room_id)
+        .bind(user_id)
+        .bind(role.as_str())
+        .bind(share_id)
+        .bind(granted_at)
+        .execute(&self.pool)
+        .await
+        .map_err(missing_room_on_fk)?;
+
+        Ok(Member {
+            user_id: user_id.to_string(),
+            role,
+            share_id: share_id.map(str::to_string),
+            granted_at,
+        })
+    }
+
+    async fn remove_member(&self, room_id: &str, user_id: &str) -> Result<(), Error> {
+        let res = sqlx::query(
+            r#"
+            DELETE FROM room_members
+            WHERE room_id = ?1
+                AND user_id = ?2"#,
+        )
+        .bind(room_id)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await
+        .map_err(Error::from)?;
+
+        if res.rows_affected() == 0 {
+            return Err(Error::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn get_member(&self, room_id: &str, user_id: &str) -> Result<Option<Member>, Error> {
+        let row = sqlx::query(
+            r#"
+            SELECT
+                user_id,
+                role,
+                share_id,
+                granted_at
+            FROM
+                room_members
+            WHERE
+                room_id = ?1
+                AND user_id = ?2"#,
+        )
+        .bind(room_id)
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Error::from)?;
+
+        if row.is_none() && !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
+        row.as_ref().map(SqliteStorage::member).transpose()
+    }
+
+    async fn list_members(&self, room_id: &str) -> Result<Vec<Member>, Error> {
+        let rows = sqlx::query(
+            r#"
+            SELECT
+                user_id,
+                role,
+                share_id,
+                granted_at
+            FROM
+                room_members
+            WHERE
+                room_id = ?1
+            ORDER BY
+                user_id ASC"#,
+        )
+        .bind(room_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::from)?;
+
+        if rows.is_empty() && !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
+        rows.iter().map(SqliteStorage::member).collect()
+    }
+
+    async fn list_memberships(&self, user_id: &str) -> Result<Vec<(String, Role)>, Error> {
+        let rows = sqlx::query(
+            r#"
+            SELECT
+                room_id,
+                role
+            FROM
+                room_members
+            WHERE
+                user_id = ?1"#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::from)?;
+
+        rows.iter()
+            .map(|r| Ok((r.try_get("room_id")?, stored_role(r.try_get("role")?)?)))
+            .collect()
+    }
+
+    async fn create_share(&self, room_id: &str, share: NewShare) -> Result<Share, Error> {
+        let created_at = Utc::now();
+        let res = sqlx::query(
+            r#"
+            INSERT INTO room_shares (share_id, room_id, token, role, created_by, expires_at,
+                max_uses, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+            ON CONFLICT
+                DO NOTHING"#,
+        )
+        .bind(&share.share_id)
+        .bind(room_id)
+        .bind(&share.token)
+        .bind(share.role.as_str())
+        .bind(&share.created_by)
+        .bind(share.expires_at)
+        .bind(share.max_uses.map(i64::from))
+        .bind(created_at)
+        .execute(&self.pool)
+        .await
+        .map_err(missing_room_on_fk)?;
+
+        if res.rows_affected() == 0 {
+            return Err(Error::AlreadyExists);
+        }
+        Ok(Share {
+            share_id: share.share_id,
+            room_id: room_id.to_string(),
+            token: share.token,
+            role: share.role,
+            created_by: share.created_by,
+            expires_at: share.expires_at,
+            max_uses: share.max_uses,
+            uses: 0,
+            created_at,
+        })
+    }
+
+    async fn list_shares(&self, room_id: &str) -> Result<Vec<Share>, Error> {
+        let rows = sqlx::query(
+            r#"
+            SELECT
+                share_id,
+                room_id,
+                token,
+                role,
+                created_by,
+                expires_at,
+                max_uses,
+                uses,
+                created_at
+            FROM
+                room_shares
+            WHERE
+                room_id = ?1
+            ORDER BY
+                julianday(created_at) ASC"#,
+        )
+        .bind(room_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::from)?;
+
+        if rows.is_empty() && !self.room_exists(room_id).await? {
+            return Err(Error::NotFound);
+        }
+
+        rows.iter().map(SqliteStorage::share).collect()
+    }
+
+    async fn delete_share(&self, room_id: &str, share_id: &str) -> Result<(), Error> {
+        let res = sqlx::query(
+            r#"
+            DELETE FROM room_shares
+            WHERE room_id = ?1
+                AND share_id = ?2"#,
+        )
+        .bind(room_id)
+        .bind(share_id)
+        .execute(&self.pool)
+        .await
+        .map_err(Error::from)?;
+
+        if res.rows_affected() == 0 {
+            return Err(Error::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn use_share(&self, token: &str, now: DateTime<Utc>) -> Result<Share, Error> {
+        let row = sqlx::query(
+            r#"
+            UPDATE
+                room_shares
+            SET
+                uses = uses + 1
+            WHERE
+                token = ?1
+                AND (expires_at IS NULL
+                    OR julianday(expires_at) > julianday(?2))
+                AND (max_uses IS NULL
+                    OR uses < max_uses)
+            RETURNING
+                share_id,
+                room_id,
+                token,
+                role,
+                created_by,
+                expires_at,
+                max_uses,
+                uses,
+                created_at"#,
+        )
+        .bind(token)
+        .bind(now)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Error::from)?
+        .ok_or(Error::NotFound)?;
+
+        SqliteStorage::share(&row)
+    }
+}
@@ -1,4 +1,6 @@
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 
 use crate::rooms::error::Error;
 
@@ -13,6 +15,17 @@ pub type LogSeq = u64;
 pub struct UpdateEntry {
     pub seq: LogSeq,
     pub bytes: Vec<u8>,
+    /// User id of whoever made the change, `None` if the server wrote it on its own.
+    pub author: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An update to append to the log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewUpdate {
+    pub bytes: Vec<u8>,
+    /// User id of whoever made the change, `None` if the server writes it on its own.
+    pub author: Option<String>,
 }
 
 /// Snapshot of a room at a specific log position.
@@ -25,13 +38,43 @@ pub struct Snapshot {
     pub bytes: Vec<u8>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct SnapshotInfo {
     pub covered_through: LogSeq,
     pub size_bytes: u64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The user behind a yrs client id that edited a room.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub client_id: u64,
+    pub user_id: String,
+    /// When the client was first recorded.
+    pub first_seen: DateTime<Utc>,
+}
+
+/// A named version of a room, like a draft, pinned to a position in its log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Version {
+    /// Unique within the room.
+    pub label: String,
+    pub seq: LogSeq,
+    /// User id of whoever created the version.
+    pub author: Option<String>,
+    pub note: Option<String>,
+    pub created_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewVersion {
+    pub label: String,
+    pub seq: LogSeq,
+    pub author: Option<String>,
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct RoomInfo {
     pub room_id: String,
 
@@ -39,12 +82,119 @@ pub struct RoomInfo {
     pub last_seq: LogSeq,
 
     pub latest_snapshot: Option<SnapshotInfo>,
+
+    /// Set if the room was forked from another one.
+    pub fork: Option<ForkPoint>,
+
+    pub title: Option<String>,
+    /// User id of whoever owns the room.
+    pub owner: Option<String>,
+    pub description: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// When an update was last appended, or the room was created if none was.
+    pub updated_at: DateTime<Utc>,
+}
+
+/// What a user may do in a room, from least to most.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Read the script and its history.
+    Viewer,
+    /// Read, and comment once comments exist.
+    Commenter,
+    /// Change the script, restore it and manage its versions.
+    Editor,
+    /// Everything, including deleting the room and granting roles.
+    Owner,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Commenter => "commenter",
+            Role::Editor => "editor",
+            Role::Owner => "owner",
+        }
+    }
+
+    /// Inverse of [`Role::as_str`].
+    pub fn parse(s: &str) -> Option<Role> {
+        match s {
+            "viewer" => Some(Role::Viewer),
+            "commenter" => Some(Role::Commenter),
+            "editor" => Some(Role::Editor),
+            "owner" => Some(Role::Owner),
+            _ => None,
+        }
+    }
+}
+
+/// A role granted to a user in a room.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Member {
+    pub user_id: String,
+    pub role: Role,
+    /// The share the role was granted through, if any. Revoking the share takes the role away.
+    pub share_id: Option<String>,
+    /// When the role was last granted.
+    pub granted_at: DateTime<Utc>,
+}
+
+/// A link granting a role in a room to whoever visits it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Share {
+    pub share_id: String,
+    pub room_id: String,
+    /// The secret that goes in the link.
+    pub token: String,
+    pub role: Role,
+    /// User id of whoever created the share.
+    pub created_by: Option<String>,
+    /// The share can't be used from then on.
+    pub expires_at: Option<DateTime<Utc>>,
+    /// How often the share can be used, `None` for no limit.
+    pub max_uses: Option<u32>,
+    pub uses: u32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewShare {
+    pub share_id: String,
+    pub token: String,
+    pub role: Role,
+    pub created_by: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub max_uses: Option<u32>,
+}
+
+/// The room and position in its log a room was forked from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ForkPoint {
+    /// Kept when the parent is deleted.
+    pub parent: String,
+    pub seq: LogSeq,
 }
 
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 #[non_exhaustive]
 pub struct CreateRoomOptions {
     pub fail_if_exists: bool,
+    pub fork: Option<ForkPoint>,
+    pub title: Option<String>,
+    pub owner: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Changes to the metadata of a room. Fields left `None` are kept as they are.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RoomMetadataUpdate {
+    pub title: Option<String>,
+    pub owner: Option<String>,
+    pub description: Option<String>,
 }
 
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
@@ -56,6 +206,13 @@ pub struct LoadUpdatesOptions {
     pub to: Option<LogSeq>,
 }
 
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PruneUpdatesOptions {
+    /// Only remove updates created strictly before this time. If None, age is not considered.
+    pub created_before: Option<DateTime<Utc>>,
+}
+
 /// A trait defining the Room Storage interface.
 #[async_trait]
 pub trait Storage: Send + Sync + 'static {
@@ -65,6 +222,18 @@ pub trait Storage: Send + Sync + 'static {
     /// Creates a room record if absent.
     async fn create_room(&self, room_id: &str, opts: CreateRoomOptions) -> Result<(), Error>;
 
+    /// Change the title, owner or description of a room. Fails with [`Error::NotFound`] if the
+    /// room does not exist.
+    async fn update_room_metadata(
+        &self,
+        room_id: &str,
+        update: RoomMetadataUpdate,
+    ) -> Result<(), Error>;
+
+    /// Makes `owner` the owner of a room that has none. Returns whether the room is theirs now,
+    /// false if it was owned before.
+    async fn claim_room(&self, room_id: &str, owner: &str) -> Result<bool, Error>;
+
     /// Removes all data for the room (metadata, updates, snapshot).
     async fn delete_room(&self, room_id: &str) -> Result<(), Error>;
 
@@ -74,24 +243,44 @@ pub trait Storage: Send + Sync + 'static {
     /// Loads metadata for one room. `None` if missing.
     async fn get_room_info(&self, room_id: &str) -> Result<Option<RoomInfo>, Error>;
 
-    /// Appends one update and returns its assigned seq.
-    async fn append_update(&self, room_id: &str, update: &[u8]) -> Result<LogSeq, Error>;
+    /// Appends one update by `author` and returns its assigned seq.
+    async fn append_update(
+        &self,
+        room_id: &str,
+        update: &[u8],
+        author: Option<&str>,
+    ) -> Result<LogSeq, Error>;
 
     /// Appends multiple updates, preserving order, and returns the assigned seq range (inclusive).
     async fn append_updates(
         &self,
         room_id: &str,
-        updates: &[Vec<u8>],
+        updates: &[NewUpdate],
     ) -> Result<(LogSeq, LogSeq), Error>;
 
     /// Loads updates in the given range as entries
-    /// (seq + bytes) for deterministic replay.
+    /// (seq + bytes + author + time) for deterministic replay.
     async fn load_updates(
         &self,
         room_id: &str,
         opts: LoadUpdatesOptions,
     ) -> Result<Vec<UpdateEntry>, Error>;
 
+    /// Returns the seq of the last update appended at or before `at`, 0 if there is none.
+    /// Only updates still in the log are considered.
+    async fn last_seq_at(&self, room_id: &str, at: DateTime<Utc>) -> Result<LogSeq, Error>;
+
+    /// Removes updates with seq <= `through` that match `opts` and returns how many were removed.
+    ///
+    /// Updates newer than the latest stored snapshot are never removed, so `through` is
+    /// clamped to that snapshot's `covered_through`.
+    async fn prune_updates_through(
+        &self,
+        room_id: &str,
+        through: LogSeq,
+        opts: PruneUpdatesOptions,
+    ) -> Result<u64, Error>;
+
     /// Store a snapshot for the given room.
     async fn store_snapshot(&self, room_id: &str, snapshot: Snapshot) -> Result<(), Error>;
 
@@ -109,6 +298,75 @@ pub trait Storage: Send + Sync + 'static {
         max_covered_through: Option<LogSeq>,
     ) -> Result<Option<Snapshot>, Error>;
 
-    /// List available snapshots for the room.
+    /// List available snapshots for the room, ordered by `covered_through` ascending.
     async fn list_snapshots(&self, room_id: &str) -> Result<Vec<SnapshotInfo>, Error>;
+
+    /// Removes the snapshots with the given `covered_through` and returns how many were removed.
+    async fn delete_snapshots(
+        &self,
+        room_id: &str,
+        covered_through: &[LogSeq],
+    ) -> Result<u64, Error>;
+
+    /// Records that the yrs client `client_id` editing the room belongs to `user_id`.
+    /// The first user recorded for a client id is kept, returns whether that is `user_id`.
+    async fn record_client(
+        &self,
+        room_id: &str,
+        client_id: u64,
+        user_id: &str,
+    ) -> Result<bool, Error>;
+
+    /// Lists the clients recorded for the room.
+    async fn list_clients(&self, room_id: &str) -> Result<Vec<ClientInfo>, Error>;
+
+    /// Stores a named version. Fails with [`Error::AlreadyExists`] if the label is taken.
+    async fn create_version(&self, room_id: &str, version: NewVersion) -> Result<Version, Error>;
+
+    /// Lists the versions of the room, ordered by `seq` ascending.
+    async fn list_versions(&self, room_id: &str) -> Result<Vec<Version>, Error>;
+
+    /// Loads one version of the room. `None` if missing.
+    async fn get_version(&self, room_id: &str, label: &str) -> Result<Option<Version>, Error>;
+
+    /// Removes a version. Fails with [`Error::NotFound`] if it does not exist.
+    async fn delete_version(&self, room_id: &str, label: &str) -> Result<(), Error>;
+
+    /// Grants `role` to a user in the room, replacing any role they had. `share_id` is the share
+    /// it is granted through, if any.
+    async fn set_member(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        role: Role,
+        share_id: Option<&str>,
+    ) -> Result<Member, Error>;
+
+    /// Takes away the role of a user in the room. Fails with [`Error::NotFound`] if they had none.
+    async fn remove_member(&self, room_id: &str, user_id: &str) -> Result<(), Error>;
+
+    /// Loads the role of a user in the room. `None` if they have none.
+    async fn get_member(&self, room_id: &str, user_id: &str) -> Result<Option<Member>, Error>;
+
+    /// Lists the users with a role in the room, ordered by `user_id`.
+    async fn list_members(&self, room_id: &str) -> Result<Vec<Member>, Error>;
+
+    /// Lists the rooms a user has a role in, with that role.
+    async fn list_memberships(&self, user_id: &str) -> Result<Vec<(String, Role)>, Error>;
+
+    /// Stores a share. Fails with [`Error::AlreadyExists`] if the id or token is taken.
+    async fn create_share(&self, room_id: &str, share: NewShare) -> Result<Share, Error>;
+
+    /// Lists the shares of the room, ordered by `created_at` ascending.
+    async fn list_shares(&self, room_id: &str) -> Result<Vec<Share>, Error>;
+
+    /// Removes a share along with the roles granted through it. Fails with [`Error::NotFound`]
+    /// if it does not exist.
+    async fn delete_share(&self, room_id: &str, share_id: &str) -> Result<(), Error>;
+
+    /// Counts a use of the share with `token` and returns it.
+    ///
+    /// Fails with [`Error::NotFound`] if there is no such share, it expired by `now` or it has
+    /// been used up.
+    async fn use_share(&self, token: &str, now: DateTime<Utc>) -> Result<Share, Error>;
 }
@@ -1,25 +1,35 @@
 use std::sync::Arc;
 
 use crate::auth::AuthManager;
-use crate::db::Db;
-use crate::rooms;
+use crate::config::Config;
 use crate::rooms::RoomManager;
+use crate::rooms::persistence::PersistenceSettings;
+use crate::rooms::storage::Storage;
 
 #[derive(Clone)]
 pub struct AppState {
-    pub db: Db,
     pub auth: AuthManager,
     pub rooms: RoomManager,
 }
 
 impl AppState {
-    pub async fn new(db: Db, auth: AuthManager) -> Self {
-        // let storage = rooms::InMemoryStorage::new().await;
-        let storage = rooms::DatabaseStorage::new(db.clone()).await;
+    pub fn new(storage: Arc<dyn Storage>, auth: AuthManager, config: &Config) -> Self {
         Self {
-            db,
             auth,
-            rooms: RoomManager::new(Arc::new(storage), 32, 100, 1024),
+            rooms: RoomManager::new(
+                storage,
+                32,
+                PersistenceSettings {
+                    queue_capacity: 1024,
+                    snapshot_every_n_updates: 100,
+                    batching: config.rooms.batching.clone(),
+                    update_retention: config.rooms.update_retention.clone(),
+                    snapshot_retention: config.rooms.snapshot_retention.clone(),
+                    retry: config.rooms.retry.clone(),
+                    journal: config.rooms.journal.clone(),
+                },
+                config.rooms.eviction.clone(),
+            ),
         }
     }
 }
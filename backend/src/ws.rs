@@ -1,2 +1,3 @@
 pub mod handler;
 pub mod peer;
+pub mod protocol;
@@ -1,9 +1,9 @@
 use axum::{
     extract::{Path, State, ws::WebSocketUpgrade},
-    http::StatusCode,
     response::IntoResponse,
 };
 
+use crate::rooms::storage::Role;
 use crate::ws;
 use crate::{auth::AuthSession, state::AppState};
 
@@ -13,17 +13,20 @@ pub async fn ws_handler(
     Path(room_id): Path<String>,
     State(state): State<AppState>,
 ) -> impl IntoResponse {
-    println!("Request for {room_id} handler!");
+    tracing::debug!(room_id, "websocket requested");
+    let role = match state
+        .rooms
+        .authorize(&room_id, &session.user_id, Role::Viewer)
+        .await
+    {
+        Ok(role) => role,
+        Err(e) => return e.into_response(),
+    };
     let room = match state.rooms.connect(&room_id).await {
         Ok(r) => r,
-        Err(e) => {
-            println!("{e:?}");
-            return StatusCode::NOT_FOUND.into_response();
-        }
+        Err(e) => return e.into_response(),
     };
 
-    let rooms = state.rooms.clone();
-    let bcast = room.bcast.clone();
-
-    ws.on_upgrade(move |socket| ws::peer::peer(socket, rooms, bcast, room_id))
+    // If the upgrade never completes the lease is dropped along with the callback.
+    ws.on_upgrade(move |socket| ws::peer::peer(socket, room, session.user_id, role))
 }
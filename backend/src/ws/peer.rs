@@ -1,24 +1,116 @@
-use axum::extract::ws::WebSocket;
-use futures_util::StreamExt;
+use axum::extract::ws::{CloseFrame, Message, WebSocket, close_code};
+use futures_util::{SinkExt, StreamExt};
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use yrs_axum::{
-    broadcast::BroadcastGroup,
-    ws::{AxumSink, AxumStream},
-};
+use std::sync::atomic::Ordering;
+use tokio::sync::{Mutex, mpsc, oneshot};
+use tokio::time::Duration;
+use yrs::block::ClientID;
+use yrs_axum::ws::{AxumSink, AxumStream};
 
-use crate::rooms::RoomManager;
+use crate::rooms::storage::Role;
+use crate::rooms::{CloseReason, RoomLease};
+use crate::ws::protocol::PeerProtocol;
 
-pub async fn peer(ws: WebSocket, rooms: RoomManager, bcast: Arc<BroadcastGroup>, room_id: String) {
+/// How long a client has to answer the close frame before the connection is cut.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Serve one connection of `user_id` to the leased room until the socket closes, or until they no
+/// longer have the `role` they connected with. Below [`Role::Editor`] the connection can't change
+/// the document.
+pub async fn peer(ws: WebSocket, room: RoomLease, user_id: String, role: Role) {
     let (sink, stream) = ws.split();
     let sink = Arc::new(Mutex::new(AxumSink::from(sink)));
-    let stream = AxumStream::from(stream);
+    // Ends the stream, and with it the subscription, once sent or dropped.
+    let (cut, cut_rx) = oneshot::channel::<()>();
+    let stream = AxumStream::from(stream).take_until(cut_rx);
+
+    let (protocol, clients) = PeerProtocol::new(user_id.clone(), role < Role::Editor);
+    let read_only = protocol.read_only();
+    let sub = room.bcast.subscribe_with(sink.clone(), stream, protocol);
+    let completed = sub.completed();
+    tokio::pin!(completed);
+
+    let serve = async {
+        tokio::select! {
+            res = &mut completed => res,
+            reason = room.close_requested(&user_id, role) => {
+                // Whatever the client sends after losing its role is not applied, even if it
+                // ignores the close frame.
+                if matches!(reason, CloseReason::Revoked | CloseReason::RoleChanged) {
+                    read_only.store(true, Ordering::Release);
+                }
+                // The connection finishes once the client answers the close frame, or is cut if
+                // it doesn't.
+                close(&sink, reason).await;
+                match tokio::time::timeout(CLOSE_TIMEOUT, &mut completed).await {
+                    Ok(res) => res,
+                    Err(_) => {
+                        tracing::warn!(room_id = room.room_id(), "close frame not answered, cutting connection");
+                        let _ = cut.send(());
+                        completed.await
+                    }
+                }
+            }
+        }
+    };
+    let (res, ()) = tokio::join!(serve, record_clients(&room, &user_id, clients));
+    match res {
+        Ok(()) => tracing::debug!(room_id = room.room_id(), "connection finished"),
+        Err(e) => {
+            tracing::warn!(room_id = room.room_id(), error = %e, "connection finished abruptly")
+        }
+    }
 
-    let sub = bcast.subscribe(sink, stream);
-    match sub.completed().await {
-        Ok(()) => println!("room={room_id} finished successfully"),
-        Err(e) => eprintln!("room={room_id} finished abruptly: {e}"),
+    room.release().await;
+}
+
+/// Record the user behind the client id the connection edits with, until it closes.
+async fn record_clients(
+    room: &RoomLease,
+    user_id: &str,
+    mut clients: mpsc::UnboundedReceiver<ClientID>,
+) {
+    while let Some(client_id) = clients.recv().await {
+        match room.record_client(client_id, user_id).await {
+            Ok(true) => {}
+            // Its edits stay credited to whoever claimed the client id first.
+            Ok(false) => {
+                tracing::warn!(
+                    room_id = room.room_id(),
+                    client_id,
+                    user_id,
+                    "client id is claimed by another user"
+                )
+            }
+            Err(e) => {
+                tracing::warn!(room_id = room.room_id(), client_id, error = ?e, "recording client failed")
+            }
+        }
     }
+}
 
-    rooms.disconnect(&room_id).await;
+/// Tell the client that the server is going away, the room is, or its access to it changed.
+async fn close(sink: &Mutex<AxumSink>, reason: CloseReason) {
+    let frame = match reason {
+        CloseReason::ShuttingDown => CloseFrame {
+            code: close_code::AWAY,
+            reason: "server is shutting down".into(),
+        },
+        CloseReason::Deleted => CloseFrame {
+            code: close_code::NORMAL,
+            reason: "room was deleted".into(),
+        },
+        CloseReason::Revoked => CloseFrame {
+            code: close_code::POLICY,
+            reason: "access was revoked".into(),
+        },
+        // Reconnecting picks up the new role.
+        CloseReason::RoleChanged => CloseFrame {
+            code: close_code::RESTART,
+            reason: "role changed".into(),
+        },
+    };
+    if let Err(e) = sink.lock().await.0.send(Message::Close(Some(frame))).await {
+        tracing::warn!(error = %e, "failed to send close frame");
+    }
 }
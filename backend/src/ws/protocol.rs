@@ -0,0 +1,109 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use tokio::sync::mpsc;
+use yrs::block::ClientID;
+use yrs::sync::awareness::AwarenessUpdate;
+use yrs::sync::{Awareness, Error, Message, Protocol};
+use yrs::{ReadTxn, Transact, Update};
+
+use crate::rooms::persistence::authored_by;
+
+/// The y-sync protocol as spoken with one connection, applying the updates it sends on behalf of
+/// its user.
+///
+/// A read-only connection still receives the document and shares awareness, but whatever it sends
+/// to change the document is dropped.
+pub struct PeerProtocol {
+    user_id: String,
+    read_only: Arc<AtomicBool>,
+    /// The client id the connection edits with, once it is known.
+    own: OnceLock<ClientID>,
+    clients: mpsc::UnboundedSender<ClientID>,
+}
+
+impl PeerProtocol {
+    /// Also returns a receiver for the client id the connection edits with, once it is known.
+    ///
+    /// That is the client id it announces its own awareness state with, or the one new client in
+    /// the first update that brings any. Updates may carry changes of other clients the connection
+    /// merely relays, those are never claimed.
+    pub fn new(user_id: String, read_only: bool) -> (Self, mpsc::UnboundedReceiver<ClientID>) {
+        let (clients, rx) = mpsc::unbounded_channel();
+        let protocol = Self {
+            user_id,
+            read_only: Arc::new(AtomicBool::new(read_only)),
+            own: OnceLock::new(),
+            clients,
+        };
+        (protocol, rx)
+    }
+
+    /// Makes the connection read-only when set, e.g. once it is being closed because its user
+    /// lost the role to edit.
+    pub fn read_only(&self) -> Arc<AtomicBool> {
+        self.read_only.clone()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Acquire)
+    }
+
+    /// Claim `client` as the connection's own, unless it already has one.
+    fn claim(&self, client: ClientID) {
+        if !self.is_read_only() && self.own.set(client).is_ok() {
+            // The receiver only goes away along with the connection.
+            let _ = self.clients.send(client);
+        }
+    }
+}
+
+impl Protocol for PeerProtocol {
+    /// Also handles `Update` messages, which are applied the same way.
+    fn handle_sync_step2(
+        &self,
+        awareness: &mut Awareness,
+        update: Update,
+    ) -> Result<Option<Message>, Error> {
+        if self.is_read_only() {
+            // Clients answer the initial sync with their state even when it holds nothing new, so
+            // this is no reason to close the connection.
+            tracing::debug!(
+                user_id = self.user_id,
+                "dropped update from read-only connection"
+            );
+            return Ok(None);
+        }
+
+        let mut txn = awareness
+            .doc()
+            .transact_mut_with(authored_by(&self.user_id));
+        if self.own.get().is_none() {
+            let known = txn.state_vector();
+            let sv = update.state_vector();
+            let mut new = sv
+                .iter()
+                .filter(|(client, _)| !known.contains_client(client));
+            if let (Some((&client, _)), None) = (new.next(), new.next()) {
+                self.claim(client);
+            }
+        }
+        txn.apply_update(update);
+        Ok(None)
+    }
+
+    fn handle_awareness_update(
+        &self,
+        awareness: &mut Awareness,
+        update: AwarenessUpdate,
+    ) -> Result<Option<Message>, Error> {
+        // Clients announce their own state by itself.
+        if update.clients.len() == 1
+            && let Some(&client) = update.clients.keys().next()
+        {
+            self.claim(client);
+        }
+        awareness.apply_update(update)?;
+        Ok(None)
+    }
+}